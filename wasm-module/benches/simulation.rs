@@ -0,0 +1,69 @@
+//! Benchmarks of the simulation through the public API, e.g. `cargo bench -- "10k sims"`.
+//! Each one also prints the heap allocations a single run makes, counted by the allocator
+//! below, since that's what reusing the deck between simulations cuts
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use criterion::{criterion_group, criterion_main, Criterion};
+use wasm_module::{ActionOutcomes, Card, UserDataStateHolder};
+
+/// The system allocator, counting every allocation
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// 16 against a dealer 10 from six decks, simulating every action `num_sims` times
+fn sixteen_against_a_ten(num_sims: u32) -> UserDataStateHolder {
+    UserDataStateHolder::new(
+        vec![Card::Ten, Card::Six],
+        vec![Card::Ten],
+        "6".to_string(),
+        "10".to_string(),
+        num_sims.to_string()
+    )
+}
+
+fn simulate(num_sims: u32) -> ActionOutcomes {
+    ActionOutcomes::simulate(sixteen_against_a_ten(num_sims)).expect("the inputs are valid")
+}
+
+fn report_allocations(name: &str, num_sims: u32) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    std::hint::black_box(simulate(num_sims));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{}: {} allocations in one run", name, allocations);
+}
+
+fn every_action(c: &mut Criterion) {
+    let name = "every action, 10k sims";
+    report_allocations(name, 10_000);
+    c.bench_function(name, |b| b.iter(|| simulate(10_000)));
+}
+
+/// The hand evaluation runs on every draw, so it's also worth timing at the sims the
+/// slider goes up to
+fn every_action_at_a_million_sims(c: &mut Criterion) {
+    let name = "every action, 1M sims";
+    report_allocations(name, 1_000_000);
+    let mut group = c.benchmark_group("million sims");
+    group.sample_size(10);
+    group.bench_function(name, |b| b.iter(|| simulate(1_000_000)));
+    group.finish();
+}
+
+criterion_group!(benches, every_action, every_action_at_a_million_sims);
+criterion_main!(benches);
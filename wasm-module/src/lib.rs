@@ -1,11 +1,223 @@
+use std::collections::HashMap;
 use std::num::{ParseFloatError, ParseIntError};
+use std::ops::ControlFlow;
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
 
-/// Generalises errors of parsing numbers
+/// Generalises errors of parsing numbers, with the name of the field that didn't parse
+#[derive(Debug)]
 enum ParseNumberError {
-    Int(ParseIntError),
-    Float(ParseFloatError)
+    Int(&'static str, ParseIntError),
+    Float(&'static str, ParseFloatError)
+}
+
+impl std::fmt::Display for ParseNumberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseNumberError::Int(field, err) => write!(f, "{} is not a valid whole number ({})", field, err),
+            ParseNumberError::Float(field, err) => write!(f, "{} is not a valid number ({})", field, err),
+        }
+    }
+}
+
+/// The kinds of failure JS can tell apart, as the name of the Error it catches
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+enum SimulationErrorKind {
+    ParseDecks,
+    ParseBet,
+    ParseSims,
+    /// A card typed as shorthand isn't a card, see parse_cards
+    ParseCards,
+    /// The player's or dealer's cards aren't a possible hand
+    InvalidHand,
+    /// A setting or rule is out of range
+    InvalidSettings,
+    /// An action's name isn't one of the names the outcomes are given, e.g. "stand"
+    UnknownAction,
+    /// The hand can't be analysed the way asked, e.g. even money without a natural
+    NotApplicable,
+    /// A strategy table has the wrong number of rows or a row of invalid codes
+    InvalidStrategy,
+    /// Generated outcomes failed their checks, see ActionOutcomes::validate
+    InvalidOutcomes,
+    /// The shoe doesn't hold the known cards, or has nothing left once they're dealt
+    DeckExhausted,
+    Cancelled,
+    /// The results couldn't be encoded to send back
+    Encoding
+}
+
+/// Why the inputs couldn't be simulated. JS receives it as a thrown Error named after the
+/// kind (e.g. "ParseDecks") so it can branch on the failure, with the message saying what
+/// exactly was wrong
+#[derive(Serialize, Debug, PartialEq)]
+struct SimulationError {
+    kind: SimulationErrorKind,
+    message: String
+}
+
+impl SimulationError {
+    fn new(kind: SimulationErrorKind, message: &str) -> Self {
+        SimulationError { kind, message: message.to_string() }
+    }
+
+    fn to_js(&self) -> JsValue {
+        let error = js_sys::Error::new(&self.message);
+        error.set_name(&format!("{:?}", self.kind));
+        error.into()
+    }
+}
+
+impl std::fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<ParseNumberError> for SimulationError {
+    fn from(error: ParseNumberError) -> Self {
+        let (ParseNumberError::Int(field, _) | ParseNumberError::Float(field, _)) = error;
+        let kind = match field {
+            "num_decks" => SimulationErrorKind::ParseDecks,
+            "bet_size" => SimulationErrorKind::ParseBet,
+            _ => SimulationErrorKind::ParseSims
+        };
+        SimulationError::new(kind, &error.to_string())
+    }
+}
+
+impl From<InvalidStateError> for SimulationError {
+    fn from(error: InvalidStateError) -> Self {
+        let kind = match error {
+            InvalidStateError::TooFewPlayerCards
+            | InvalidStateError::NoDealerCards
+            | InvalidStateError::DealerBust(_) => SimulationErrorKind::InvalidHand,
+            InvalidStateError::MoreCardsThanTheShoe(..)
+            | InvalidStateError::NoCardsLeft => SimulationErrorKind::DeckExhausted,
+            _ => SimulationErrorKind::InvalidSettings
+        };
+        SimulationError::new(kind, &error.to_string())
+    }
+}
+
+impl From<InvalidOutcomesError> for SimulationError {
+    fn from(error: InvalidOutcomesError) -> Self {
+        SimulationError::new(SimulationErrorKind::InvalidOutcomes, &error.to_string())
+    }
+}
+
+impl From<rmp_serde::encode::Error> for SimulationError {
+    fn from(error: rmp_serde::encode::Error) -> Self {
+        SimulationError::new(SimulationErrorKind::Encoding, &format!("the results couldn't be encoded ({})", error))
+    }
+}
+
+/// Table rules that change which actions are offered or how they play out
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RulesConfig {
+    /// Allows doubling on a three-card hand, which offers the "hit then double" action
+    pub double_after_hit: bool,
+    /// The total hands bust above, 21 except in teaching variants (e.g. "what if the game
+    /// targeted 25"). The dealer stands 4 below it, i.e. on 17 in the normal game
+    pub target_total: u8,
+    /// What a natural pays as a multiple of the bet, 1.5 for the usual 3:2
+    pub blackjack_payout: f64,
+    /// The dealer hits a soft 17 (H17) rather than standing on it (S17)
+    pub dealer_hits_soft_17: bool,
+    /// The fraction of the shoe dealt before it's reshuffled, when whole rounds are played
+    /// from one shoe (the single-hand analysis always starts from a fresh shoe)
+    pub penetration: f64,
+    /// Drawing from a continuous shoe, where every card is drawn with its fixed chance (4/13
+    /// for a ten-value, 1/13 for each other rank) regardless of the cards already seen. This
+    /// is the textbook approximation, and much faster since there's no deck to track
+    pub infinite_deck: bool,
+    /// Allows doubling a hand after splitting (DAS). Each split hand doubles on its first
+    /// two cards whenever that's worth more than hitting or standing on them
+    pub double_after_split: bool,
+    /// The most hands a pair can be split into, where a card that pairs a split hand again
+    /// is split off into another hand until there are this many. 2 means no resplitting
+    pub max_split_hands: u8,
+    /// A "five card Charlie": a hand of five or more cards that hasn't bust wins whatever the
+    /// dealer finishes on (though not against a dealer natural, which ends the round first)
+    pub five_card_charlie: bool,
+    /// The total the dealer stands on in the normal game, 17 in the usual rules. Another
+    /// target total moves it by as much as the target moves (e.g. 21 under a target of 25)
+    pub dealer_stand_value: u8,
+    /// European no hole card (ENHC) rules: the dealer takes their second card only after the
+    /// player has acted, so there's no peek, and a dealer natural then takes everything the
+    /// player has bet, doubles and splits included
+    pub european_no_hole_card: bool
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        RulesConfig {
+            double_after_hit: false,
+            target_total: 21,
+            blackjack_payout: BLACKJACK_PAYOUT,
+            dealer_hits_soft_17: false,
+            penetration: 0.75,
+            infinite_deck: false,
+            double_after_split: false,
+            max_split_hands: 2,
+            five_card_charlie: false,
+            dealer_stand_value: 17,
+            european_no_hole_card: false
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl RulesConfig {
+    /// The default rules, where nothing beyond the basic game is allowed
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl RulesConfig {
+    /// Checks the rules describe a game the simulation can play
+    fn validate(&self) -> Result<(), InvalidStateError> {
+        if !TARGET_TOTALS.contains(&self.target_total) {
+            return Err(InvalidStateError::TargetTotalOutOfRange(self.target_total));
+        }
+        if !(self.blackjack_payout.is_finite() && self.blackjack_payout >= 0.0) {
+            return Err(InvalidStateError::InvalidBlackjackPayout(self.blackjack_payout));
+        }
+        if !(self.penetration > 0.0 && self.penetration <= 1.0) {
+            return Err(InvalidStateError::PenetrationOutOfRange(self.penetration));
+        }
+        if self.max_split_hands < 2 {
+            return Err(InvalidStateError::TooFewSplitHands(self.max_split_hands));
+        }
+        if !DEALER_STAND_VALUES.contains(&self.dealer_stand_value) {
+            return Err(InvalidStateError::DealerStandValueOutOfRange(self.dealer_stand_value));
+        }
+        Ok(())
+    }
+
+    /// The total the dealer stands on under the rules' target total
+    fn dealer_stand_total(&self) -> u8 {
+        self.dealer_stand_value + (self.target_total - 21)
+    }
+}
+
+/// The range of bust thresholds the simulation supports for the target_total rule
+const TARGET_TOTALS: std::ops::RangeInclusive<u8> = 21..=31;
+
+/// The range of totals the dealer can be made to stand on with the dealer_stand_value rule
+const DEALER_STAND_VALUES: std::ops::RangeInclusive<u8> = 12..=21;
+
+/// What to do with a simulation when the shoe runs out of cards before the hand is over
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DeckEmptyPolicy {
+    /// Settle the hands as they are, as if everyone stood
+    #[default]
+    TreatAsStand,
+    /// Leave the simulation out of the results, which are then over fewer simulations
+    DiscardSim,
 }
 
 /// This is used by the JS code to store user inputs and send it to Rust code
@@ -15,7 +227,19 @@ pub struct UserDataStateHolder {
     dealer_card: Vec<Card>,
     num_decks: String,
     bet_size: String,
-    num_sims: String
+    num_sims: String,
+    count_bias: f64,
+    fixed_dealer_total: Option<u8>,
+    on_deck_empty: DeckEmptyPolicy,
+    importance_sampling: bool,
+    exact_enumeration: bool,
+    rules: RulesConfig,
+    seed: Option<u64>,
+    seen_cards: Vec<Card>,
+    dealer_hole_card: Card,
+    custom_deck: Vec<Card>,
+    num_other_players: u8,
+    target_ci_width: Option<f64>
 }
 
 #[wasm_bindgen]
@@ -33,17 +257,119 @@ impl UserDataStateHolder {
             num_decks,
             bet_size,
             num_sims,
+            count_bias: 0.0,
+            fixed_dealer_total: None,
+            on_deck_empty: DeckEmptyPolicy::TreatAsStand,
+            importance_sampling: false,
+            exact_enumeration: false,
+            rules: RulesConfig::new(),
+            seed: None,
+            seen_cards: Vec::new(),
+            dealer_hole_card: Card::Empty,
+            custom_deck: Vec::new(),
+            num_other_players: 0,
+            target_ci_width: None,
         }
     }
 
+    /// Biases the shoe to reflect a Hi-Lo true count, e.g. +2 makes tens and aces
+    /// more likely to be drawn than in a fresh shoe
+    pub fn set_count_bias(&mut self, true_count: f64) {
+        self.count_bias = true_count;
+    }
+
+    /// Worst-case analysis: rather than simulating the dealer's hand, assume it always
+    /// finishes on this total (17 to 21). None goes back to simulating the dealer
+    pub fn set_fixed_dealer_total(&mut self, total: Option<u8>) {
+        self.fixed_dealer_total = total;
+    }
+
+    /// Chooses what happens to a simulation that runs out of cards (only possible with very
+    /// few cards left in the shoe)
+    pub fn set_on_deck_empty(&mut self, policy: DeckEmptyPolicy) {
+        self.on_deck_empty = policy;
+    }
+
+    /// Importance samples the dealer's draws (see DealerDrawTilt), which estimates rare
+    /// outcomes like losing a stand on 20 much more precisely for the same number of
+    /// simulations. Only the summary results are reweighted, so streamed and session
+    /// results always simulate normally
+    pub fn set_importance_sampling(&mut self, enabled: bool) {
+        self.importance_sampling = enabled;
+    }
+
+    /// Works out standing and hitting once exactly, by enumerating every card that can be
+    /// drawn, rather than simulating them (see exact_shallow_outcomes). Exact results have no
+    /// raw counts or sampling error, and deeper draws are still simulated
+    pub fn set_exact_enumeration(&mut self, enabled: bool) {
+        self.exact_enumeration = enabled;
+    }
+
+    /// Sets the table rules to simulate under, otherwise the default rules are used
+    pub fn set_rules(&mut self, rules: &RulesConfig) {
+        self.rules = *rules;
+    }
+
+    /// Seeds the simulation so the same inputs always give the same results, None goes back
+    /// to fresh randomness. Every action is simulated from the same seed
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    /// The cards already dealt from this shoe in earlier rounds, which are taken out of the
+    /// shoe along with the player's and dealer's cards. An infinite deck ignores them
+    pub fn set_seen_cards(&mut self, seen_cards: Vec<Card>) {
+        self.seen_cards = seen_cards;
+    }
+
+    /// The dealer's hole card when it's known, e.g. reviewing a hand after it was turned over.
+    /// The dealer draws on from it (rather than from a hidden card), and Empty goes back to
+    /// not knowing it
+    pub fn set_dealer_hole_card(&mut self, card: Card) {
+        self.dealer_hole_card = card;
+    }
+
+    /// Plays from exactly these cards instead of num_decks standard decks, e.g. a shoe with
+    /// every ten taken out. The player's, dealer's and seen cards still come out of it, and
+    /// an empty list goes back to the standard shoe
+    pub fn set_custom_deck(&mut self, cards: Vec<Card>) {
+        self.custom_deck = cards;
+    }
+
+    /// The other players at the table (at most 6), who are each dealt two cards from the
+    /// shoe before every simulated round. Their hands aren't played out, but the cards they
+    /// hold are gone before the player and dealer draw
+    pub fn set_num_other_players(&mut self, num_other_players: u8) {
+        self.num_other_players = num_other_players;
+    }
+
+    /// Stops simulating an action early once the 95% confidence interval of its EV is
+    /// narrow enough, i.e. 1.96 standard errors (in dollars) fall below this. num_sims is
+    /// still the most that are run, and each result's num_sims says how many were. None
+    /// always runs them all
+    pub fn set_target_ci_width(&mut self, target_ci_width: Option<f64>) {
+        self.target_ci_width = target_ci_width;
+    }
+
     /// Converts this to a UserDataState, which is used by Rust
     /// This parses data and handles errors if there are any
+    #[allow(clippy::wrong_self_convention)]
     fn to_user_data_state(self) -> Result<UserDataState, ParseNumberError> {
         let current_cards = self.current_cards
             .into_iter()
             .filter(|card| *card != Card::Empty)
             .collect();
+        // a known hole card is just another of the dealer's cards
         let dealer_card = self.dealer_card
+            .into_iter()
+            .chain(std::iter::once(self.dealer_hole_card))
+            .filter(|card| *card != Card::Empty)
+            .collect();
+        let seen_cards = self.seen_cards
+            .into_iter()
+            .filter(|card| *card != Card::Empty)
+            .collect();
+        let custom_deck = self.custom_deck
             .into_iter()
             .filter(|card| *card != Card::Empty)
             .collect();
@@ -51,15 +377,15 @@ impl UserDataStateHolder {
         // parse and return err if we cannot parse it
         let num_decks: u8 = match self.num_decks.parse::<u8>() {
             Ok(value) => value,
-            Err(err) => return Err(ParseNumberError::Int(err))
+            Err(err) => return Err(ParseNumberError::Int("num_decks", err))
         };
         let bet_size: f64 = match self.bet_size.parse::<f64>() {
             Ok(value) => value,
-            Err(err) => return Err(ParseNumberError::Float(err))
+            Err(err) => return Err(ParseNumberError::Float("bet_size", err))
         };
         let num_sims: u32 = match self.num_sims.parse::<u32>() {
             Ok(value) => value,
-            Err(err) => return Err(ParseNumberError::Int(err))
+            Err(err) => return Err(ParseNumberError::Int("num_sims", err))
         };
 
         Ok(UserDataState {
@@ -67,35 +393,227 @@ impl UserDataStateHolder {
             dealer_card,
             num_decks,
             bet_size,
-            num_sims
+            num_sims,
+            count_bias: self.count_bias,
+            fixed_dealer_total: self.fixed_dealer_total,
+            on_deck_empty: self.on_deck_empty,
+            importance_sampling: self.importance_sampling,
+            exact_enumeration: self.exact_enumeration,
+            rules: self.rules,
+            seed: self.seed,
+            seen_cards,
+            custom_deck,
+            num_other_players: self.num_other_players,
+            target_ci_width: self.target_ci_width
         })
     }
 }
 
+/// Parses user inputs into a game state, returning an Err saying which input was wrong if
+/// it doesn't parse or is not a possible BJ game (see SimulationError)
+fn parse_valid_state(data: UserDataStateHolder) -> Result<UserDataState, JsValue> {
+    parse_state(data).map_err(|error| error.to_js())
+}
+
+/// parse_valid_state without the JS error, so the error can be checked natively
+fn parse_state(data: UserDataStateHolder) -> Result<UserDataState, SimulationError> {
+    let data = data.to_user_data_state()?;
+    data.validate()?;
+    Ok(data)
+}
+
+/// Real games use at most 8 decks, anything much bigger only slows the simulation down
+const MAX_DECKS: u8 = 12;
+
+/// A table seats seven, so there are at most six other players
+const MAX_OTHER_PLAYERS: u8 = 6;
+
+/// Reasons a parsed game state is not a possible BJ game
+#[derive(Debug, PartialEq)]
+enum InvalidStateError {
+    TooFewPlayerCards,
+    NoDealerCards,
+    DealerBust(u8),
+    NoDecks,
+    TooManyDecks(u8),
+    NoSimulations,
+    InvalidBetSize(f64),
+    TooManyOtherPlayers(u8),
+    TargetCiWidthOutOfRange(f64),
+    FixedDealerTotalOutOfRange(u8),
+    TargetTotalOutOfRange(u8),
+    InvalidBlackjackPayout(f64),
+    PenetrationOutOfRange(f64),
+    TooFewSplitHands(u8),
+    DealerStandValueOutOfRange(u8),
+    MoreCardsThanTheShoe(Card, usize),
+    NoCardsLeft
+}
+
+impl std::fmt::Display for InvalidStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InvalidStateError::TooFewPlayerCards => write!(f, "the player needs at least two cards"),
+            InvalidStateError::NoDealerCards => write!(f, "the dealer needs at least one card"),
+            InvalidStateError::DealerBust(total) => write!(f, "the dealer's cards already bust on {}", total),
+            InvalidStateError::NoDecks => write!(f, "at least one deck is needed"),
+            InvalidStateError::TooManyDecks(num_decks) =>
+                write!(f, "{} decks is more than the maximum of {}", num_decks, MAX_DECKS),
+            InvalidStateError::NoSimulations => write!(f, "at least one simulation is needed"),
+            InvalidStateError::InvalidBetSize(bet_size) =>
+                write!(f, "a bet of {} isn't possible, it must be a number above 0", bet_size),
+            InvalidStateError::TooManyOtherPlayers(num_other_players) => write!(
+                f, "{} other players is more than the {} other seats at a table",
+                num_other_players, MAX_OTHER_PLAYERS
+            ),
+            InvalidStateError::TargetCiWidthOutOfRange(width) =>
+                write!(f, "a confidence interval width of {} isn't possible, it must be above 0", width),
+            InvalidStateError::FixedDealerTotalOutOfRange(total) =>
+                write!(f, "the dealer can't finish on {}, it must be 17 to 21", total),
+            InvalidStateError::TargetTotalOutOfRange(total) => write!(
+                f, "a target total of {} isn't supported, it must be {} to {}",
+                total, TARGET_TOTALS.start(), TARGET_TOTALS.end()
+            ),
+            InvalidStateError::InvalidBlackjackPayout(payout) =>
+                write!(f, "a blackjack payout of {} isn't possible, it can't be negative", payout),
+            InvalidStateError::PenetrationOutOfRange(penetration) =>
+                write!(f, "a penetration of {} isn't possible, it must be above 0 and at most 1", penetration),
+            InvalidStateError::TooFewSplitHands(max_split_hands) =>
+                write!(f, "a split makes at least 2 hands, so a limit of {} isn't possible", max_split_hands),
+            InvalidStateError::DealerStandValueOutOfRange(value) => write!(
+                f, "the dealer can't stand on {}, it must be {} to {}",
+                value, DEALER_STAND_VALUES.start(), DEALER_STAND_VALUES.end()
+            ),
+            InvalidStateError::MoreCardsThanTheShoe(card, count) =>
+                write!(f, "{} of {:?} were entered, more than the shoe holds", count, card),
+            InvalidStateError::NoCardsLeft => write!(f, "every card in the shoe has already been seen"),
+        }
+    }
+}
+
+/// The Hi-Lo count of every card dealt from the shoe so far, as a counter would track it
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CountSummary {
+    running_count: i32,
+    /// Cards left in the shoe over 52, not rounded
+    decks_remaining: f64,
+    true_count: f64
+}
+
+/// Whether to take even money on a natural against a dealer ace, with EVs in dollars
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct EvenMoneyDecision {
+    dealer_natural_probability: f64,
+    even_money_ev: f64,
+    /// Standing on the natural instead, which wins the blackjack payout unless the dealer
+    /// also has a natural
+    decline_ev: f64,
+    take_even_money: bool
+}
+
+/// The simulation's recommendation next to what textbook basic strategy plays for the hand
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct StrategyComparison {
+    /// The label of the best simulated action, e.g. "hit_once"
+    simulated_action: String,
+    /// "hit", "stand", "double" or "split", see textbook_action
+    basic_strategy_action: String,
+    /// Whether the simulated action is the same kind of move, so any number of hits agrees
+    /// with "hit". A disagreement is usually the count (or the rules) changing the play
+    agrees: bool
+}
+
 /// Struct that stores values for our monte carlo simulation
 /// We can be sure that at this stage, values have been sanitised
+#[derive(Clone, Default)]
 struct UserDataState {
     current_cards: Vec<Card>,
     dealer_card: Vec<Card>,
     num_decks: u8,
     bet_size: f64,
-    num_sims: u32
+    num_sims: u32,
+    count_bias: f64,
+    fixed_dealer_total: Option<u8>,
+    on_deck_empty: DeckEmptyPolicy,
+    importance_sampling: bool,
+    exact_enumeration: bool,
+    rules: RulesConfig,
+    seed: Option<u64>,
+    seen_cards: Vec<Card>,
+    /// The cards the shoe starts from in place of num_decks standard decks, when not empty
+    custom_deck: Vec<Card>,
+    num_other_players: u8,
+    target_ci_width: Option<f64>
 }
 
 impl UserDataState {
-    /// Checks the validity of user inputs (it must have a possible state of a BJ game)
-    fn is_valid(&self) -> bool {
-        self.current_cards.len() >= 2
-            && self.dealer_card.len() == 1
-            && self.num_decks >= 1
-            && self.num_sims >= 1
+    /// Checks the validity of user inputs (it must have a possible state of a BJ game),
+    /// saying why it isn't if not
+    fn validate(&self) -> Result<(), InvalidStateError> {
+        if self.current_cards.len() < 2 { return Err(InvalidStateError::TooFewPlayerCards); }
+        // the dealer can show more than their upcard when a played-out hand is re-evaluated,
+        // and draws on from those cards, which mustn't already be bust
+        if self.dealer_card.is_empty() { return Err(InvalidStateError::NoDealerCards); }
+        let (dealer_total, _) = classify_hand(&self.dealer_card);
+        if dealer_total > self.rules.target_total { return Err(InvalidStateError::DealerBust(dealer_total)); }
+        if self.num_decks < 1 { return Err(InvalidStateError::NoDecks); }
+        if self.num_decks > MAX_DECKS { return Err(InvalidStateError::TooManyDecks(self.num_decks)); }
+        if self.num_sims < 1 { return Err(InvalidStateError::NoSimulations); }
+        // EVs scale with the bet, so a negative one would rank the actions backwards
+        if !(self.bet_size.is_finite() && self.bet_size > 0.0) {
+            return Err(InvalidStateError::InvalidBetSize(self.bet_size));
+        }
+        if self.num_other_players > MAX_OTHER_PLAYERS {
+            return Err(InvalidStateError::TooManyOtherPlayers(self.num_other_players));
+        }
+        if let Some(width) = self.target_ci_width.filter(|width| !(width.is_finite() && *width > 0.0)) {
+            return Err(InvalidStateError::TargetCiWidthOutOfRange(width));
+        }
+        if let Some(total) = self.fixed_dealer_total.filter(|total| !(17..=21).contains(total)) {
+            return Err(InvalidStateError::FixedDealerTotalOutOfRange(total));
+        }
+
+        // each card is in the shoe 4 times per deck (or however many times the custom deck
+        // has it), while an infinite deck never runs out. Empty cards were already filtered out
+        if !self.rules.infinite_deck {
+            let shoe = self.full_shoe();
+            let known_cards: Vec<&Card> = self.current_cards.iter()
+                .chain(&self.dealer_card)
+                .chain(&self.seen_cards)
+                .collect();
+            for card in &known_cards {
+                let count = known_cards.iter().filter(|&other| other == card).count();
+                if count > shoe.cards.iter().filter(|other| other == card).count() {
+                    return Err(InvalidStateError::MoreCardsThanTheShoe((*card).clone(), count));
+                }
+            }
+            // the simulation needs something left to draw
+            if known_cards.len() >= shoe.cards.len() {
+                return Err(InvalidStateError::NoCardsLeft);
+            }
+        }
+        self.rules.validate()
+    }
+
+    /// Seeds the simulation with `seed` if there is one, otherwise keeps the seed already set
+    fn with_seed(self, seed: Option<u64>) -> Self {
+        UserDataState { seed: seed.or(self.seed), ..self }
+    }
+
+    /// The shoe before any cards are dealt from it: the custom deck if there is one, otherwise
+    /// num_decks standard decks
+    fn full_shoe(&self) -> Deck {
+        match self.custom_deck.is_empty() {
+            true => Deck::new(&self.num_decks),
+            false => Deck { cards: self.custom_deck.clone() }
+        }
     }
 }
 
-/// Enum type for BJ cards
+/// Enum type for BJ cards, ordered by rank with Ace low and Empty before every card
 #[derive(Clone)]
 #[wasm_bindgen]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Card {
     Empty, // would prefer to use an Option if JS could make them
     Ace,
@@ -113,7 +631,53 @@ pub enum Card {
     King,
 }
 
+/// One card of each rank in a standard deck
+const DECK_CARDS: [Card; 13] = [
+    Card::Ace, Card::Two, Card::Three, Card::Four,
+    Card::Five, Card::Six, Card::Seven, Card::Eight,
+    Card::Nine, Card::Ten, Card::Jack, Card::Queen,
+    Card::King
+];
+
+/// One card of each value, in the order used for rank counts (ten-value cards share the Ten slot)
+const RANKS: [Card; 10] = [
+    Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five,
+    Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten
+];
+
 impl Card {
+    /// Hi-Lo counting tag: +1 for 2-6, 0 for 7-9 and -1 for tens and aces
+    fn hi_lo_tag(&self) -> i8 {
+        match self {
+            Card::Two | Card::Three | Card::Four | Card::Five | Card::Six => 1,
+            Card::Seven | Card::Eight | Card::Nine | Card::Empty => 0,
+            Card::Ten | Card::Jack | Card::Queen | Card::King | Card::Ace => -1,
+        }
+    }
+
+    /// Position of this card's value in RANKS, Empty has no rank
+    fn rank_index(&self) -> Option<usize> {
+        match self {
+            Card::Empty => None,
+            Card::Ace => Some(0),
+            Card::Two => Some(1),
+            Card::Three => Some(2),
+            Card::Four => Some(3),
+            Card::Five => Some(4),
+            Card::Six => Some(5),
+            Card::Seven => Some(6),
+            Card::Eight => Some(7),
+            Card::Nine => Some(8),
+            Card::Ten | Card::Jack | Card::Queen | Card::King => Some(9),
+        }
+    }
+
+    /// The card's value counting an ace as 1, without allocating like get_card_values.
+    /// Empty is worth nothing
+    fn hard_value(&self) -> u8 {
+        self.rank_index().map_or(0, |index| index as u8 + 1)
+    }
+
     /// Stores a list of values for each Card, this must be a list since Ace equals 1 or 11
     fn get_card_values(&self) -> Vec<u8> {
         match self {
@@ -133,6 +697,65 @@ impl Card {
             Card::King => vec![10],
         }
     }
+
+    /// The card's rank as it's usually written, e.g. "A", "10" or "K". Empty has no name
+    fn short_name(&self) -> &'static str {
+        match self {
+            Card::Empty => "",
+            Card::Ace => "A",
+            Card::Two => "2",
+            Card::Three => "3",
+            Card::Four => "4",
+            Card::Five => "5",
+            Card::Six => "6",
+            Card::Seven => "7",
+            Card::Eight => "8",
+            Card::Nine => "9",
+            Card::Ten => "10",
+            Card::Jack => "J",
+            Card::Queen => "Q",
+            Card::King => "K",
+        }
+    }
+
+    /// Reads a card from shorthand like "A", "10", "t" or "QD", ignoring any suit letter
+    fn from_short_name(token: &str) -> Option<Card> {
+        let token = token.to_uppercase();
+        let rank = match token.strip_suffix(['S', 'H', 'D', 'C']) {
+            Some(rank) if !rank.is_empty() => rank,
+            _ => &token
+        };
+        if rank == "T" { return Some(Card::Ten); }
+        DECK_CARDS.into_iter().find(|card| card.short_name() == rank)
+    }
+}
+
+/// Parses a hand typed as shorthand, e.g. "A 10 K" or "AS,TH,QD", where cards are separated
+/// by spaces and/or commas in any case, suits are ignored and "T" is a ten. Errors with the
+/// first token that isn't a card
+#[wasm_bindgen]
+pub fn parse_cards(input: &str) -> Result<Vec<Card>, JsValue> {
+    parse_hand(input).map_err(|error| error.to_js())
+}
+
+/// parse_cards without the JS error, so the error can be checked natively
+fn parse_hand(input: &str) -> Result<Vec<Card>, SimulationError> {
+    parse_card_tokens(input).map_err(|message| SimulationError::new(SimulationErrorKind::ParseCards, &message))
+}
+
+/// parse_cards for native callers, erroring with the message rather than a JsValue
+pub fn parse_card_tokens(input: &str) -> Result<Vec<Card>, String> {
+    input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .map(|token| Card::from_short_name(token).ok_or_else(|| format!("{:?} is not a card", token)))
+        .collect()
+}
+
+/// The shorthand for a card that parse_cards reads back, e.g. "A" or "10"
+#[wasm_bindgen]
+pub fn card_to_string(card: Card) -> String {
+    card.short_name().to_string()
 }
 
 /// Stores a list of cards for the entire deck
@@ -147,17 +770,10 @@ impl Deck {
     pub fn new(
         num_decks: &u8
     ) -> Self {
-        // list of each card
-        let mut cards = vec![
-            Card::Ace, Card::Two, Card::Three, Card::Four,
-            Card::Five, Card::Six, Card::Seven, Card::Eight,
-            Card::Nine, Card::Ten, Card::Jack, Card::Queen,
-            Card::King];
-
         // duplicate 4 times (to make a deck) and num_deck times to make number of decks
-        cards = cards
+        let cards = DECK_CARDS
             .into_iter()
-            .flat_map(|x: Card| std::iter::repeat(x).take((4 * num_decks) as usize))
+            .flat_map(|x: Card| std::iter::repeat_n(x, (4 * num_decks) as usize))
             .collect::<Vec<Card>>();
 
         Deck {
@@ -173,28 +789,217 @@ impl Deck {
         }
     }
 
+    /// Reshapes the shoe to match a Hi-Lo true count while keeping its size. A true count
+    /// of +1 per remaining deck means the low cards (2-6) seen outnumber the high cards
+    /// (10-A) seen by one, so half a card per deck moves from the low ranks to the high ranks,
+    /// spread proportionally over the ranks in each group. The result is rounded to whole
+    /// cards, handing out the rounding remainder by largest fraction (ties to the lower rank)
+    fn apply_count_bias(&mut self, true_count: f64) {
+        let total = self.cards.len();
+        if total == 0 || true_count == 0.0 { return; }
+
+        let counts: Vec<f64> = DECK_CARDS
+            .iter()
+            .map(|rank| self.cards.iter().filter(|card| *card == rank).count() as f64)
+            .collect();
+        let group_total = |group: i8| DECK_CARDS
+            .iter()
+            .zip(counts.iter())
+            .filter(|(card, _)| card.hi_lo_tag() == group)
+            .map(|(_, count)| count)
+            .sum::<f64>();
+        let low_total = group_total(1);
+        let high_total = group_total(-1);
+
+        let decks_remaining = total as f64 / 52.0;
+        let shift = (true_count * decks_remaining / 2.0).clamp(-high_total, low_total);
+
+        // exhausted groups stay exhausted, so their share of the shift is dropped
+        let targets: Vec<f64> = DECK_CARDS
+            .iter()
+            .zip(counts.iter())
+            .map(|(card, &count)| match card.hi_lo_tag() {
+                1 if low_total > 0.0 => count * (low_total - shift) / low_total,
+                -1 if high_total > 0.0 => count * (high_total + shift) / high_total,
+                _ => count
+            })
+            .collect();
+        let target_total: f64 = targets.iter().sum();
+        let targets: Vec<f64> = targets
+            .into_iter()
+            .map(|target| target * total as f64 / target_total)
+            .collect();
+
+        let mut rounded: Vec<usize> = targets.iter().map(|target| target.floor() as usize).collect();
+        let mut by_remainder: Vec<usize> = (0..targets.len()).collect();
+        by_remainder.sort_by(|&a, &b| (targets[b] - targets[b].floor())
+            .total_cmp(&(targets[a] - targets[a].floor()))
+            .then(a.cmp(&b)));
+        let shortfall = total - rounded.iter().sum::<usize>();
+        by_remainder.into_iter().take(shortfall).for_each(|index| rounded[index] += 1);
+
+        self.cards = DECK_CARDS
+            .into_iter()
+            .zip(rounded)
+            .flat_map(|(card, count)| std::iter::repeat_n(card, count))
+            .collect();
+    }
+
+    /// Counts the cards left in the deck for each of RANKS
+    fn rank_counts(&self) -> [u32; 10] {
+        let mut counts = [0; 10];
+        self.cards
+            .iter()
+            .filter_map(|card| card.rank_index())
+            .for_each(|index| counts[index] += 1);
+        counts
+    }
+
     /// Takes a random card from the deck and returns it, useful for drawing a new card
-    /// in our simulation.
-    pub fn take_random_card_from_deck(&mut self) -> Card {
-        match getrandom::u64() {
-            Ok(value) => {
-                let random_index = (value % self.cards.len() as u64) as usize;
-                self.cards.remove(random_index)
+    /// in our simulation. Returns None once the deck is empty
+    pub fn take_random_card_from_deck(&mut self) -> Option<Card> {
+        self.take_random_card_using(&mut SimRng::Entropy)
+    }
+
+    /// Like take_random_card_from_deck, drawing with the given source of randomness. The
+    /// first card is taken only if the platform's entropy fails. Every draw is uniform over
+    /// the cards left, so their order doesn't matter and the last card fills the gap
+    fn take_random_card_using(&mut self, rng: &mut SimRng) -> Option<Card> {
+        if self.cards.is_empty() { return None; }
+
+        let index = rng.next_index(self.cards.len()).unwrap_or(0);
+        Some(self.cards.swap_remove(index))
+    }
+
+    /// A random card from the deck, which is left as it is
+    fn random_card_using(&self, rng: &mut SimRng) -> Option<Card> {
+        if self.cards.is_empty() { return None; }
+
+        let index = rng.next_index(self.cards.len()).unwrap_or(0);
+        Some(self.cards[index].clone())
+    }
+
+    /// Refills this deck with `deck`'s cards, reusing its allocation rather than cloning afresh
+    fn refill_from(&mut self, deck: &Deck) {
+        self.cards.clone_from(&deck.cards);
+    }
+}
+
+/// Where a simulation's randomness comes from: the platform's entropy, or a generator
+/// (SplitMix64) seeded by the caller, which makes a run reproducible
+enum SimRng {
+    Entropy,
+    Seeded(u64)
+}
+
+impl SimRng {
+    fn from_seed(seed: Option<u64>) -> Self {
+        seed.map_or(SimRng::Entropy, SimRng::Seeded)
+    }
+
+    /// The randomness for simulation number `sim` of a seeded run, as the dealer's and the
+    /// player's streams, both mixed from the seed and the number. Runs of different actions
+    /// from the same seed then share common random numbers: their i-th simulations have the
+    /// same hole card, and since the dealer draws from their own stream, the dealer picks
+    /// alike however many cards the player took. The noise in each action's EV is then mostly
+    /// shared, so the differences between them are more precise than with independent
+    /// simulations. Without a seed every simulation is fresh
+    fn for_simulation(seed: Option<u64>, sim: u32) -> (Self, Self) {
+        match seed {
+            // mixing both the seed and then the number in, rather than adding or xor-ing them
+            // straight in, keeps every stream apart from its neighbours' and other seeds'
+            Some(seed) => {
+                let mixed_seed = SimRng::Seeded(seed).next_u64().unwrap_or(seed);
+                let mut streams = SimRng::Seeded(mixed_seed ^ sim as u64);
+                let dealer_seed = streams.next_u64().unwrap_or(seed);
+                let player_seed = streams.next_u64().unwrap_or(seed);
+                (SimRng::Seeded(dealer_seed), SimRng::Seeded(player_seed))
             }
-            Err(_) => {
-                self.cards.remove(0)
+            None => (SimRng::Entropy, SimRng::Entropy)
+        }
+    }
+
+    /// The next random number, None only if the platform's entropy fails
+    fn next_u64(&mut self) -> Option<u64> {
+        match self {
+            SimRng::Entropy => getrandom::u64().ok(),
+            SimRng::Seeded(state) => {
+                *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut value = *state;
+                value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                Some(value ^ (value >> 31))
+            }
+        }
+    }
+
+    /// A uniformly random index below `len` (which must be above 0), using Lemire's
+    /// multiply-and-reject method to avoid the bias `value % len` has towards low indices
+    fn next_index(&mut self, len: usize) -> Option<usize> {
+        let range = len as u64;
+        // 2^64 mod range, the number of values that would be over-represented
+        let threshold = range.wrapping_neg() % range;
+        loop {
+            let product = self.next_u64()? as u128 * range as u128;
+            if product as u64 >= threshold {
+                return Some((product >> 64) as usize);
             }
         }
     }
+
+    /// A uniformly random number in [0, 1)
+    fn next_unit(&mut self) -> f64 {
+        self.next_u64().map_or(0.0, |value| (value >> 11) as f64 / (1u64 << 53) as f64)
+    }
 }
 
 /// Holder for the data we want to send to JS
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 struct ProbabilityValueOutcomes {
     estimated_value: f64,
     win: f64,
     loss: f64,
-    tie: f64
+    tie: f64,
+    /// Wins with a natural (the player's original two cards make 21), included in win
+    blackjack: f64,
+    /// The player's hand busting, included in loss. Split hands each count as their share of
+    /// the simulation, as they do for win, loss and tie
+    bust: f64,
+    applicable: bool,
+    average_cards_drawn: f64,
+    stake: f64,
+    /// EV as a multiple of the bet, which is exact even when the stake depends on the cards drawn
+    ev_per_bet: f64,
+    /// What the blackjacks paid as a multiple of the stake
+    blackjack_payout: f64,
+    /// Standard error of estimated_value (in dollars), absent with fewer than two simulations
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    std_error: Option<f64>,
+    /// Binomial standard errors of win and loss, sqrt(p * (1 - p) / n), which are 0 rather
+    /// than undefined for a single simulation
+    win_std_error: f64,
+    loss_std_error: f64,
+    /// Raw counts behind the probabilities, unweighted by importance sampling. Each split hand
+    /// counts separately, and num_sims is the number of simulations counted (not discarded)
+    wins: u32,
+    losses: u32,
+    ties: u32,
+    num_sims: u32,
+    /// Simulations left out because the shoe ran out of cards (see DeckEmptyPolicy)
+    discarded_sims: u32,
+    /// Most common final totals (0 means bust) and the fraction of simulations ending on them
+    modal_player_total: u8,
+    modal_player_total_frequency: f64,
+    modal_dealer_total: u8,
+    modal_dealer_total_frequency: f64,
+    /// Worked out exactly rather than simulated, so there are no raw counts and no sampling error
+    exact: bool,
+    /// Variance of one simulation's net result in bets, e.g. both hands of a split together,
+    /// which is the sample variance when simulated (0 with fewer than two simulations)
+    net_variance: f64,
+    /// Why the action can't be taken, only present when it isn't applicable
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    reason: Option<String>
 }
 
 impl ProbabilityValueOutcomes {
@@ -205,409 +1010,6178 @@ impl ProbabilityValueOutcomes {
             win: 0.5,
             loss: 0.5,
             tie: 0.0,
+            blackjack: 0.0,
+            bust: 0.0,
+            applicable: true,
+            average_cards_drawn: 0.0,
+            stake: 1.0,
+            ev_per_bet: 0.0,
+            blackjack_payout: BLACKJACK_PAYOUT,
+            std_error: None,
+            win_std_error: 0.0,
+            loss_std_error: 0.0,
+            wins: 0,
+            losses: 0,
+            ties: 0,
+            num_sims: 0,
+            discarded_sims: 0,
+            modal_player_total: 0,
+            modal_player_total_frequency: 0.0,
+            modal_dealer_total: 0,
+            modal_dealer_total_frequency: 0.0,
+            exact: false,
+            net_variance: 0.0,
+            reason: None,
         }
     }
-}
-
-/// Enum holder for different game outcomes
-#[derive(Debug, PartialEq)]
-enum GameOutcome {
-    WIN,
-    LOSS,
-    TIE
-}
-
-/// Holder for different BJ actions, HIT and SPLIT have u8s to
-/// store the number of times the player will hit (e.g. SPLIT(2) means split and hit twice)
-enum BlackJackAction {
-    HIT(u8),
-    STAND,
-    SPLIT(u8)
-}
-
-/// Holder for the different actions to send back to JS
-#[wasm_bindgen]
-#[derive(Serialize, Deserialize)]
-pub struct ActionOutcomes {
-    hit_once: ProbabilityValueOutcomes,
-    hit_twice: ProbabilityValueOutcomes,
-    hit_thrice: ProbabilityValueOutcomes,
-    stand: ProbabilityValueOutcomes,
-    split_hit_once: ProbabilityValueOutcomes,
-    split_hit_twice: ProbabilityValueOutcomes,
-    split_hit_thrice: ProbabilityValueOutcomes,
-}
 
-#[wasm_bindgen]
-impl ActionOutcomes {
-    pub fn new() -> Self {
-        ActionOutcomes {
-            hit_once: ProbabilityValueOutcomes::new(),
-            hit_twice: ProbabilityValueOutcomes::new(),
-            hit_thrice: ProbabilityValueOutcomes::new(),
-            stand: ProbabilityValueOutcomes::new(),
-            split_hit_once: ProbabilityValueOutcomes::new(),
-            split_hit_twice: ProbabilityValueOutcomes::new(),
-            split_hit_thrice: ProbabilityValueOutcomes::new(),
+    /// Default outcomes for an action the player cannot take with their hand
+    fn not_applicable(reason: &str) -> Self {
+        ProbabilityValueOutcomes {
+            applicable: false,
+            reason: Some(reason.to_string()),
+            ..ProbabilityValueOutcomes::new()
         }
     }
 
-    /// Sets each action value to default without changing the object
-    fn clear(&mut self) {
-        self.hit_once = ProbabilityValueOutcomes::new();
-        self.hit_twice = ProbabilityValueOutcomes::new();
-        self.hit_thrice = ProbabilityValueOutcomes::new();
-        self.stand = ProbabilityValueOutcomes::new();
-        self.split_hit_once = ProbabilityValueOutcomes::new();
-        self.split_hit_twice = ProbabilityValueOutcomes::new();
-        self.split_hit_thrice = ProbabilityValueOutcomes::new();
+    /// Recomputes the EV at a different bet size from the stored EV per bet,
+    /// so the UI can rescale EVs without re-simulating. Actions that stake more than
+    /// the original bet (e.g. doubling) win or lose that multiple of it
+    pub fn ev_at_bet(&self, bet: f64) -> f64 {
+        self.ev_per_bet * bet
     }
 
-    /// Generates probabilities and EVs for all possible moves given BJ game state
-    pub fn generate_all_action_outcomes(&mut self, data: UserDataStateHolder) -> Result<JsValue, JsValue> {
-        let data: UserDataState = match data.to_user_data_state() {
-            Ok(value) => value,
-            Err(_) => return Err(Default::default())
-        };
+    /// Replaces non-finite values (e.g. from a zero simulation count) and clamps
+    /// probabilities to [0, 1] so JS never receives NaN or Inf
+    fn sanitise(&mut self) {
+        self.estimated_value = finite_or_zero(self.estimated_value);
+        self.ev_per_bet = finite_or_zero(self.ev_per_bet);
+        self.win = finite_or_zero(self.win).clamp(0.0, 1.0);
+        self.loss = finite_or_zero(self.loss).clamp(0.0, 1.0);
+        self.tie = finite_or_zero(self.tie).clamp(0.0, 1.0);
+        self.blackjack = finite_or_zero(self.blackjack).clamp(0.0, self.win);
+        self.bust = finite_or_zero(self.bust).clamp(0.0, self.loss);
+        self.average_cards_drawn = finite_or_zero(self.average_cards_drawn);
+        self.std_error = self.std_error.filter(|std_error| std_error.is_finite());
+        self.win_std_error = finite_or_zero(self.win_std_error);
+        self.loss_std_error = finite_or_zero(self.loss_std_error);
+    }
 
-        if !data.is_valid() { return Err(Default::default()); }
+    /// Multiplies every probability (but not EVs or totals) by the factor, e.g. 100 for percentages
+    fn scale_probabilities(&mut self, factor: f64) {
+        self.win *= factor;
+        self.loss *= factor;
+        self.tie *= factor;
+        self.blackjack *= factor;
+        self.bust *= factor;
+        self.win_std_error *= factor;
+        self.loss_std_error *= factor;
+        self.modal_player_total_frequency *= factor;
+        self.modal_dealer_total_frequency *= factor;
+    }
 
-        // currently we "hit" 6 times but could bring this down to 3 - unsure if this would
-        // make it much faster however.
-        self.hit_once = self.generate_outcomes(&data, BlackJackAction::HIT(1));
-        self.hit_twice = self.generate_outcomes(&data, BlackJackAction::HIT(2));
-        self.hit_thrice = self.generate_outcomes(&data, BlackJackAction::HIT(3));
+    /// The probability of each outcome keyed by name, where "win" excludes the naturals
+    /// counted under "blackjack" so the values sum to one
+    pub fn outcomes_map(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.outcome_probabilities()).unwrap_or_default()
+    }
 
-        self.stand = self.generate_outcomes(&data, BlackJackAction::STAND);
+    fn outcome_probabilities(&self) -> std::collections::BTreeMap<&'static str, f64> {
+        std::collections::BTreeMap::from([
+            ("win", self.win - self.blackjack),
+            ("blackjack", self.blackjack),
+            ("loss", self.loss),
+            ("tie", self.tie),
+        ])
+    }
 
-        if can_split_hand(&data.current_cards) {
-            self.split_hit_once = self.generate_outcomes(&data, BlackJackAction::SPLIT(1));
-            self.split_hit_twice = self.generate_outcomes(&data, BlackJackAction::SPLIT(2));
-            self.split_hit_thrice = self.generate_outcomes(&data, BlackJackAction::SPLIT(3));
-        }
+    /// Variance of the net result of one round, measured in bets (see net_variance)
+    pub fn unit_variance(&self) -> f64 {
+        self.net_variance
+    }
 
-        let response = Ok(serde_wasm_bindgen::to_value(&self)?);
-        self.clear();
+    /// The variance of the net result worked out from the probabilities, which is only right
+    /// for one hand at a fixed stake (ties contribute 0). Blackjacks are the wins that pay the
+    /// blackjack payout rather than even money. The exact outcomes have no samples to take
+    /// the variance of, and are all of that kind
+    fn single_hand_variance(&self) -> f64 {
+        let even_wins = self.win - self.blackjack;
+        let payout = self.blackjack_payout;
+        let unit_ev = even_wins + self.blackjack * payout - self.loss;
+        let second_moment = even_wins + self.blackjack * payout * payout + self.loss;
+        (second_moment - unit_ev * unit_ev) * self.stake * self.stake
+    }
+}
+
+/// Divides a count by the number of simulations, returning 0 rather than NaN when there were none
+fn ratio(count: u64, num_sims: u32) -> f64 {
+    if num_sims == 0 {
+        0.0
+    } else {
+        count as f64 / num_sims as f64
+    }
+}
+
+/// Standard error of a probability estimated from `num_sims` simulations, 0 when there were
+/// none. The probability is clamped into [0, 1] so weighted estimates can't make it negative
+fn binomial_std_error(probability: f64, num_sims: f64) -> f64 {
+    if num_sims > 0.0 {
+        let probability = probability.clamp(0.0, 1.0);
+        (probability * (1.0 - probability) / num_sims).sqrt()
+    } else {
+        0.0
+    }
+}
+
+/// Like ratio, but for weighted counts (importance sampled simulations have weights other than 1)
+fn weighted_ratio(weight: f64, num_sims: f64) -> f64 {
+    if num_sims > 0.0 {
+        weight / num_sims
+    } else {
+        0.0
+    }
+}
+
+/// The most common total in a (weighted) count of final totals (indexed by total, 0 for bust)
+/// and how often it occurred, preferring the lower total when two are equally common
+fn modal_total(total_weights: &[f64], num_sims: f64) -> (u8, f64) {
+    let (total, weight) = total_weights
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap_or((0, &0.0));
+    (total as u8, weighted_ratio(*weight, num_sims))
+}
+
+/// Maps NaN and infinite values to 0
+fn finite_or_zero(value: f64) -> f64 {
+    if value.is_finite() { value } else { 0.0 }
+}
+
+/// Probabilities of each of the dealer's final totals
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct DealerTotals {
+    seventeen: f64,
+    eighteen: f64,
+    nineteen: f64,
+    twenty: f64,
+    twenty_one: f64,
+    bust: f64,
+    /// Standing below 17, which only a dealer_stand_value under 17 allows
+    under_seventeen: f64
+}
+
+/// Counts the dealer's final totals over many simulations
+#[derive(Default)]
+struct DealerTotalsCounter {
+    counts: [u64; 7], // 17, 18, 19, 20, 21, bust and anything under 17
+    num_sims: u32
+}
+
+impl DealerTotalsCounter {
+    /// Records one of the dealer's final totals (None if they bust)
+    fn record(&mut self, dealer_total: Option<u8>) {
+        self.num_sims += 1;
+        match dealer_total {
+            Some(total @ 17..=21) => self.counts[(total - 17) as usize] += 1,
+            None => self.counts[5] += 1,
+            Some(_) => self.counts[6] += 1
+        }
+    }
+
+    fn to_dealer_totals(&self) -> DealerTotals {
+        let probability = |index: usize| ratio(self.counts[index], self.num_sims);
+        DealerTotals {
+            seventeen: probability(0),
+            eighteen: probability(1),
+            nineteen: probability(2),
+            twenty: probability(3),
+            twenty_one: probability(4),
+            bust: probability(5),
+            under_seventeen: probability(6)
+        }
+    }
+}
+
+/// The outcome of standing on the current hand, with the dealer's final totals attached
+#[derive(Serialize, Deserialize)]
+struct StandResolution {
+    stand: ProbabilityValueOutcomes,
+    dealer_totals: DealerTotals
+}
+
+/// Probabilities of each of the player's final best totals, 4 to 21 then bust
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct PlayerTotals {
+    /// totals[i] is the chance of finishing on a total of 4 + i
+    totals: [f64; 18],
+    bust: f64
+}
+
+/// Counts the player's final totals over many simulations
+#[derive(Default)]
+struct PlayerTotalsCounter {
+    counts: [u64; 19], // 4 to 21 and bust
+    num_sims: u32
+}
+
+impl PlayerTotalsCounter {
+    /// Records one of the player's final hands
+    fn record(&mut self, player_cards: &[Card]) {
+        self.num_sims += 1;
+        match best_hand_total(player_cards) {
+            Some(total @ 4..=21) => self.counts[(total - 4) as usize] += 1,
+            None => self.counts[18] += 1,
+            Some(_) => {} // every hand has at least two cards, so at least 4
+        }
+    }
+
+    fn to_player_totals(&self) -> PlayerTotals {
+        PlayerTotals {
+            totals: std::array::from_fn(|index| ratio(self.counts[index], self.num_sims)),
+            bust: ratio(self.counts[18], self.num_sims)
+        }
+    }
+}
+
+/// The outcome of an action, with the player's final totals attached
+#[derive(Serialize, Deserialize)]
+struct PlayerTotalsResolution {
+    outcomes: ProbabilityValueOutcomes,
+    player_totals: PlayerTotals
+}
+
+/// How often a session finished on a net result, rounded to whole bets
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SessionBucket {
+    net_units: i64,
+    probability: f64
+}
+
+/// The spread of net results over many sessions of the same hand played at a flat bet
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SessionResults {
+    action: String,
+    hands_per_session: u32,
+    /// Sessions played out, fewer than asked for if simulations were discarded when the
+    /// shoe ran out (see DeckEmptyPolicy)
+    num_sessions: u32,
+    /// Mean net result of a session in dollars
+    mean: f64,
+    buckets: Vec<SessionBucket>
+}
+
+/// How a bankroll fares over many trajectories of the same hand played repeatedly. Bankrolls
+/// are in dollars
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct BankrollRisk {
+    action: String,
+    /// The fraction of trajectories that went broke before the last hand
+    ruin_probability: f64,
+    median_final_bankroll: f64,
+    p5_final_bankroll: f64,
+    p95_final_bankroll: f64
+}
+
+/// A bankroll below a cent can't place any bet, so it's broke
+const BROKE_BELOW: f64 = 0.01;
+
+/// The EV and variance (both in dollars) of one hand at a given bet size
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct BetSweepPoint {
+    bet: f64,
+    ev: f64,
+    variance: f64
+}
+
+/// How the recommended action's EV and variance change across bet sizes
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct BetSizeSweep {
+    action: String,
+    points: Vec<BetSweepPoint>
+}
+
+/// Enum holder for different game outcomes, serialized as "win", "loss" or "tie"
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum GameOutcome {
+    WIN,
+    LOSS,
+    TIE
+}
+
+/// Holder for different BJ actions, HIT and SPLIT have u8s to
+/// store the number of times the player will hit (e.g. SPLIT(2) means split and hit twice)
+/// HIT_TO stores the total the player keeps hitting until they reach (or bust)
+/// DOUBLE doubles the bet, takes exactly one card and stands
+/// SURRENDER gives up half the bet and ends the hand before the dealer plays
+/// DOUBLE_FOR stores the extra stake as a fraction of the bet (1.0 is a full double down)
+/// HIT_THEN_OPTIMAL hits the given number of times then plays EV-optimal hit/stand (0 is pure optimal play)
+/// HIT_THEN_DOUBLE hits once then doubles on the three-card hand (unless the first card busts it)
+/// Serialized with the action's name next to its value, if it has one, e.g.
+/// {"action": "split", "value": 2} or {"action": "stand"}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "action", content = "value", rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+enum BlackJackAction {
+    HIT(u8),
+    HIT_TO(u8),
+    HIT_THEN_OPTIMAL(u8),
+    HIT_THEN_DOUBLE,
+    STAND,
+    SURRENDER,
+    DOUBLE,
+    DOUBLE_FOR(f64),
+    SPLIT(u8)
+}
+
+/// The actions that can be recommended, for JS to match on rather than comparing labels
+#[wasm_bindgen]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum RecommendedAction {
+    Stand,
+    HitOnce,
+    HitTwice,
+    HitThrice,
+    HitOnceThenOptimal,
+    HitThenDouble,
+    DoubleDown,
+    Surrender,
+    SplitHitOnce,
+    SplitHitTwice,
+    SplitHitThrice
+}
+
+impl RecommendedAction {
+    /// The action for one of ActionOutcomes' labels, None for insurance and play_optimal
+    /// (which are never recommended) or an unknown label
+    fn from_label(label: &str) -> Option<Self> {
+        match label {
+            "stand" => Some(RecommendedAction::Stand),
+            "hit_once" => Some(RecommendedAction::HitOnce),
+            "hit_twice" => Some(RecommendedAction::HitTwice),
+            "hit_thrice" => Some(RecommendedAction::HitThrice),
+            "hit_once_then_optimal" => Some(RecommendedAction::HitOnceThenOptimal),
+            "hit_then_double" => Some(RecommendedAction::HitThenDouble),
+            "double_down" => Some(RecommendedAction::DoubleDown),
+            "surrender" => Some(RecommendedAction::Surrender),
+            "split_hit_once" => Some(RecommendedAction::SplitHitOnce),
+            "split_hit_twice" => Some(RecommendedAction::SplitHitTwice),
+            "split_hit_thrice" => Some(RecommendedAction::SplitHitThrice),
+            _ => None
+        }
+    }
+}
+
+/// Insurance is a side bet of half the main bet
+const INSURANCE_STAKE: f64 = 0.5;
+
+/// EVs closer than this are treated as equal when recommending an action
+const EV_TIE_TOLERANCE: f64 = 1e-9;
+
+/// Simulations in the pilot run used to estimate how many are needed to rank the top two actions
+const SEPARATION_PILOT_SIMS: u32 = 2_000;
+
+/// How far an action's outcome probabilities may sum from one before they're considered broken
+const PROBABILITY_SUM_TOLERANCE: f64 = 1e-6;
+
+/// Reasons generated outcomes can't be trusted
+#[derive(Debug, PartialEq)]
+enum InvalidOutcomesError {
+    NotFinite(&'static str),
+    ProbabilitiesDontSumToOne(&'static str, f64),
+    BlackjackExceedsWins(&'static str)
+}
+
+impl std::fmt::Display for InvalidOutcomesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InvalidOutcomesError::NotFinite(action) => write!(f, "{} has a value that isn't finite", action),
+            InvalidOutcomesError::ProbabilitiesDontSumToOne(action, sum) =>
+                write!(f, "{}'s outcome probabilities sum to {} rather than 1", action, sum),
+            InvalidOutcomesError::BlackjackExceedsWins(action) =>
+                write!(f, "{} has more blackjacks than wins", action),
+        }
+    }
+}
+
+/// A player natural that the dealer doesn't match pays 3:2
+const BLACKJACK_PAYOUT: f64 = 1.5;
+
+/// Policy for choosing between actions whose EVs are within floating-point noise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TieBreak {
+    /// Keep the first action in the fixed order (stand, hits, then splits)
+    FixedOrder,
+    /// Prefer the action with the lowest variance in its net result
+    LowerVariance,
+    /// Prefer the action that risks the smallest stake (e.g. stand over split)
+    LowerStake,
+}
+
+/// How a set of outcomes was produced, to judge how stable they are
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct SimulationMetadata {
+    /// The most simulations run for any action, which is fewer than asked for when every
+    /// action stopped early at the target_ci_width
+    num_sims: u32,
+    /// Wall-clock time to simulate every action, None without a JS performance clock
+    elapsed_ms: Option<f64>,
+    /// Cards left in the shoe once the known cards are taken out
+    deck_size: usize
+}
+
+/// The outcomes generate_all_action_outcomes sends back, keyed by action as before with the
+/// metadata alongside them
+#[derive(Serialize)]
+struct ActionOutcomesResponse<'a> {
+    #[serde(flatten)]
+    outcomes: &'a ActionOutcomes,
+    metadata: SimulationMetadata
+}
+
+/// A batch entry for a state that couldn't be simulated, in place of its outcomes
+#[derive(Serialize)]
+struct BatchEntryError<'a> {
+    error: &'a SimulationError
+}
+
+/// What generate_batch sends back for each state, its response or its error
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchEntryResponse<'a> {
+    Outcomes(ActionOutcomesResponse<'a>),
+    Error(BatchEntryError<'a>)
+}
+
+/// One state's outcomes from a batch with how they were produced, or why they couldn't be
+type BatchEntry = Result<(ActionOutcomes, SimulationMetadata), SimulationError>;
+
+fn batch_responses(entries: &[BatchEntry]) -> Vec<BatchEntryResponse<'_>> {
+    entries.iter().map(|entry| match entry {
+        Ok((outcomes, metadata)) => BatchEntryResponse::Outcomes(
+            ActionOutcomesResponse { outcomes, metadata: metadata.clone() }
+        ),
+        Err(error) => BatchEntryResponse::Error(BatchEntryError { error })
+    }).collect()
+}
+
+/// Serializes a batch to MessagePack, keeping field names so JS decodes the same shape as
+/// generate_batch returns
+fn batch_to_msgpack(entries: &[BatchEntry]) -> Result<Vec<u8>, SimulationError> {
+    Ok(rmp_serde::to_vec_named(&batch_responses(entries))?)
+}
+
+fn simulation_metadata(data: &UserDataState, outcomes: &ActionOutcomes, elapsed_ms: Option<f64>) -> SimulationMetadata {
+    SimulationMetadata {
+        num_sims: outcomes.all_outcomes()
+            .into_iter()
+            .map(|outcomes| outcomes.num_sims + outcomes.discarded_sims)
+            .max()
+            .unwrap_or(0),
+        elapsed_ms,
+        deck_size: remaining_deck(data).cards.len()
+    }
+}
+
+/// Milliseconds on the JS performance clock, None where there isn't one (natively, or in a
+/// JS environment without the performance API)
+fn performance_now() -> Option<f64> {
+    if !cfg!(target_arch = "wasm32") { return None; }
+
+    let performance = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("performance")).ok()?;
+    let now = js_sys::Reflect::get(&performance, &JsValue::from_str("now")).ok()?;
+    now.dyn_into::<js_sys::Function>().ok()?.call0(&performance).ok()?.as_f64()
+}
+
+/// Holder for the different actions to send back to JS
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ActionOutcomes {
+    hit_once: ProbabilityValueOutcomes,
+    hit_twice: ProbabilityValueOutcomes,
+    hit_thrice: ProbabilityValueOutcomes,
+    hit_once_then_optimal: ProbabilityValueOutcomes,
+    /// Hitting for as long as it's worth more than standing (see HitStandSolver), with no
+    /// limit on the number of cards. It's the best of standing and hitting once then playing
+    /// optimally, so it's shown for comparison but never recommended
+    play_optimal: ProbabilityValueOutcomes,
+    hit_then_double: ProbabilityValueOutcomes,
+    double_down: ProbabilityValueOutcomes,
+    surrender: ProbabilityValueOutcomes,
+    /// The insurance side bet, which isn't a way of playing the hand so is never recommended
+    insurance: ProbabilityValueOutcomes,
+    stand: ProbabilityValueOutcomes,
+    split_hit_once: ProbabilityValueOutcomes,
+    split_hit_twice: ProbabilityValueOutcomes,
+    split_hit_thrice: ProbabilityValueOutcomes,
+    /// Whether probabilities are percentages (0-100) rather than fractions (0-1)
+    as_percent: bool,
+    /// Advice that goes with the recommendation, e.g. when every action loses money
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    note: Option<String>,
+}
+
+impl Default for ActionOutcomes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl ActionOutcomes {
+    pub fn new() -> Self {
+        ActionOutcomes {
+            hit_once: ProbabilityValueOutcomes::new(),
+            hit_twice: ProbabilityValueOutcomes::new(),
+            hit_thrice: ProbabilityValueOutcomes::new(),
+            hit_once_then_optimal: ProbabilityValueOutcomes::new(),
+            play_optimal: ProbabilityValueOutcomes::new(),
+            hit_then_double: ProbabilityValueOutcomes::new(),
+            double_down: ProbabilityValueOutcomes::new(),
+            surrender: ProbabilityValueOutcomes::new(),
+            insurance: ProbabilityValueOutcomes::new(),
+            stand: ProbabilityValueOutcomes::new(),
+            split_hit_once: ProbabilityValueOutcomes::new(),
+            split_hit_twice: ProbabilityValueOutcomes::new(),
+            split_hit_thrice: ProbabilityValueOutcomes::new(),
+            as_percent: false,
+            note: None,
+        }
+    }
+
+    /// Checks that every applicable action's win, loss and tie probabilities sum to one (wins
+    /// include blackjacks) and that all its values are finite, erroring with what's wrong.
+    /// Importance sampled estimates are only unbiased rather than normalised so may not pass
+    pub fn validate(&self) -> Result<(), JsValue> {
+        self.check_outcomes().map_err(|error| SimulationError::from(error).to_js())
+    }
+
+    /// The name of the best action after generate_all_action_outcomes, i.e. the applicable
+    /// one with the highest EV, preferring stand, then fewer hits, on equal EVs. Actions the
+    /// hand can't take (e.g. splitting a non-pair) are never recommended
+    pub fn best_action(&self) -> String {
+        self.recommend_best_action(TieBreak::FixedOrder)
+    }
+
+    /// best_action as an enum JS can match on
+    pub fn best_action_kind(&self) -> RecommendedAction {
+        RecommendedAction::from_label(&self.best_action()).unwrap_or(RecommendedAction::Stand)
+    }
+
+    /// The move best_action makes, e.g. {"action": "hit", "value": 1} for hitting once (see
+    /// BlackJackAction)
+    pub fn best_action_details(&self) -> Result<JsValue, JsValue> {
+        let action = action_for_label(&self.best_action()).unwrap_or(BlackJackAction::STAND);
+        Ok(serde_wasm_bindgen::to_value(&action)?)
+    }
+
+    /// Reports probabilities as percentages (0-100) instead of fractions (0-1) from now on
+    pub fn set_as_percent(&mut self, as_percent: bool) {
+        self.as_percent = as_percent;
+    }
+
+    /// Sets each action value to default without changing the object
+    fn clear(&mut self) {
+        self.hit_once = ProbabilityValueOutcomes::new();
+        self.hit_twice = ProbabilityValueOutcomes::new();
+        self.hit_thrice = ProbabilityValueOutcomes::new();
+        self.hit_once_then_optimal = ProbabilityValueOutcomes::new();
+        self.play_optimal = ProbabilityValueOutcomes::new();
+        self.hit_then_double = ProbabilityValueOutcomes::new();
+        self.double_down = ProbabilityValueOutcomes::new();
+        self.surrender = ProbabilityValueOutcomes::new();
+        self.insurance = ProbabilityValueOutcomes::new();
+        self.stand = ProbabilityValueOutcomes::new();
+        self.split_hit_once = ProbabilityValueOutcomes::new();
+        self.split_hit_twice = ProbabilityValueOutcomes::new();
+        self.split_hit_thrice = ProbabilityValueOutcomes::new();
+        self.note = None;
+    }
+
+    /// Generates probabilities and EVs for all possible moves given BJ game state. If given,
+    /// `progress` is called with the fraction of all the actions' simulations completed (0-1)
+    /// each time it passes another percent, and `should_cancel` is polled every
+    /// CANCEL_CHECK_SIMS simulations (or more often) to abandon the run by returning true.
+    /// A cancelled run errors with "cancelled" rather than returning partial outcomes.
+    /// The response also has a "metadata" entry describing the run (see SimulationMetadata).
+    /// A `seed` (a BigInt) replaces any set on the data, so the same inputs always give the
+    /// same outputs. Every action then draws the same sequence of cards, which compares them
+    /// fairly since none is luckier with the shoe than another
+    pub fn generate_all_action_outcomes(
+        &mut self,
+        data: UserDataStateHolder,
+        progress: Option<js_sys::Function>,
+        should_cancel: Option<js_sys::Function>,
+        seed: Option<u64>
+    ) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?.with_seed(seed);
+        let started_at = performance_now();
+
+        let mut result = Ok(());
+        let completed = self.populate_action_outcomes_with_progress(
+            &data,
+            &mut |fraction| {
+                if let (Some(progress), Ok(())) = (&progress, &result) {
+                    result = progress.call1(&JsValue::NULL, &JsValue::from_f64(fraction)).map(|_| ());
+                }
+            },
+            // a callback that throws cancels too, there's no way to keep asking it
+            &mut || should_cancel
+                .as_ref()
+                .is_some_and(|should_cancel| should_cancel.call0(&JsValue::NULL).map_or(true, |cancel| cancel.is_truthy()))
+        );
+        if !completed { result = Err(SimulationError::new(SimulationErrorKind::Cancelled, "cancelled").to_js()); }
+        if let Err(error) = result {
+            self.clear();
+            return Err(error);
+        }
+        self.apply_output_units();
+
+        let elapsed_ms = started_at.zip(performance_now()).map(|(start, end)| end - start);
+        let response = ActionOutcomesResponse { outcomes: self, metadata: simulation_metadata(&data, self, elapsed_ms) };
+        let response = Ok(serde_wasm_bindgen::to_value(&response)?);
+        self.clear();
         response
     }
 
-    /// Generates probabilities and EVs for a single action
-    fn generate_outcomes(&self, data: &UserDataState, action: BlackJackAction) -> ProbabilityValueOutcomes {
-        let mut wins = 0;
-        let mut losses = 0;
-        let mut ties = 0;
-
-        // remove known cards in dealer/player hands from deck
-        let mut deck = Deck::new(&data.num_decks);
-        data.current_cards.iter().for_each(|card| deck.remove_card_from_deck(card));
-        data.dealer_card.iter().for_each(|card| deck.remove_card_from_deck(card));
-
-        for _ in 0..data.num_sims {
-            let mut current_deck = deck.clone();
-            let draw_card = &mut || current_deck.take_random_card_from_deck();
-
-            let mut player_cards = data.current_cards.clone();
-            handle_player_action(
-                &mut player_cards,
-                &action,
-                draw_card
-            );
+    /// Same as generate_all_action_outcomes, but serialized as MessagePack bytes instead of
+    /// a JS object, which is much smaller when transferring many results
+    pub fn generate_all_action_outcomes_msgpack(&mut self, data: UserDataStateHolder) -> Result<Vec<u8>, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        self.populate_action_outcomes(&data);
+        self.apply_output_units();
+
+        let response = self.to_msgpack().map_err(|error| SimulationError::from(error).to_js());
+        self.clear();
+        response
+    }
+
+    /// Simulates each of `states` like generate_all_action_outcomes (without progress or
+    /// cancelling), returning an array of their responses in the same order, so a whole
+    /// strategy grid costs one call into WASM. An invalid state doesn't fail the batch: its
+    /// entry is `{ error: { kind, message } }` instead, the kind naming the Error
+    /// generate_all_action_outcomes would throw, and the other states are still simulated.
+    /// Every state has its own shoe, so the simulations themselves aren't shared
+    pub fn generate_batch(&self, states: Vec<UserDataStateHolder>) -> Result<JsValue, JsValue> {
+        let entries = self.simulate_batch(states);
+        Ok(serde_wasm_bindgen::to_value(&batch_responses(&entries))?)
+    }
+
+    /// Same as generate_batch, but serialized as MessagePack bytes like
+    /// generate_all_action_outcomes_msgpack, which is where the size saving matters most
+    pub fn generate_batch_msgpack(&self, states: Vec<UserDataStateHolder>) -> Result<Vec<u8>, JsValue> {
+        let entries = self.simulate_batch(states);
+        batch_to_msgpack(&entries).map_err(|error| error.to_js())
+    }
+
+    /// generate_batch's entries, each simulated with this instance's output settings
+    fn simulate_batch(&self, states: Vec<UserDataStateHolder>) -> Vec<BatchEntry> {
+        states.into_iter().map(|state| {
+            let data = parse_state(state)?;
+            let started_at = performance_now();
+
+            let mut outcomes = ActionOutcomes { as_percent: self.as_percent, ..ActionOutcomes::new() };
+            outcomes.populate_action_outcomes(&data);
+            outcomes.apply_output_units();
+
+            let elapsed_ms = started_at.zip(performance_now()).map(|(start, end)| end - start);
+            let metadata = simulation_metadata(&data, &outcomes, elapsed_ms);
+            Ok((outcomes, metadata))
+        }).collect()
+    }
+
+    /// Serializes the outcomes to MessagePack, keeping field names so JS decodes the same shape as JSON
+    fn to_msgpack(&self) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+        rmp_serde::to_vec_named(self)
+    }
+
+    /// Resolves a hand where the player has already stood, so the only randomness is the
+    /// dealer's draw. This avoids computing every other action and also returns the
+    /// distribution of the dealer's final totals. Only the normal game (a target total of
+    /// 21) is bucketed
+    pub fn resolve_stand(&self, data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        let mut resolution = self.generate_stand_resolution(&data).map_err(|error| error.to_js())?;
+        resolution.stand.sanitise();
+        Ok(serde_wasm_bindgen::to_value(&resolution)?)
+    }
+
+    /// Simulates every action and compares the best one with the textbook basic strategy
+    /// play for the hand
+    pub fn compare_with_basic_strategy(&mut self, data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        self.populate_action_outcomes(&data);
+        let comparison = self.strategy_comparison(&data);
+        self.clear();
+        Ok(serde_wasm_bindgen::to_value(&comparison)?)
+    }
+
+    fn generate_stand_resolution(&self, data: &UserDataState) -> Result<StandResolution, SimulationError> {
+        if data.rules.target_total != 21 {
+            return Err(SimulationError::new(SimulationErrorKind::NotApplicable, "dealer totals are only bucketed with a target total of 21"));
+        }
+
+        let mut dealer_totals = DealerTotalsCounter::default();
+        let stand = self.generate_outcomes_with(
+            data,
+            BlackJackAction::STAND,
+            &mut |_, dealer_total, _| dealer_totals.record(dealer_total)
+        );
+
+        Ok(StandResolution {
+            stand,
+            dealer_totals: dealer_totals.to_dealer_totals()
+        })
+    }
+
+    /// Simulates the named action like generate_all_action_outcomes does, also returning the
+    /// distribution of the player's final totals (of the first hand after a split) to show
+    /// why the action has its EV. Only the normal game (a target total of 21) is bucketed
+    pub fn resolve_with_player_totals(&self, data: UserDataStateHolder, action: String) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+        let action = parse_action(&action).map_err(|error| error.to_js())?;
+
+        let mut resolution = self.generate_player_totals_resolution(&data, action).map_err(|error| error.to_js())?;
+        resolution.outcomes.sanitise();
+        Ok(serde_wasm_bindgen::to_value(&resolution)?)
+    }
+
+    fn generate_player_totals_resolution(
+        &self,
+        data: &UserDataState,
+        action: BlackJackAction
+    ) -> Result<PlayerTotalsResolution, SimulationError> {
+        if data.rules.target_total != 21 {
+            return Err(SimulationError::new(SimulationErrorKind::NotApplicable, "player totals are only bucketed with a target total of 21"));
+        }
+
+        let mut player_totals = PlayerTotalsCounter::default();
+        let outcomes = self.generate_outcomes_with(
+            data,
+            action,
+            &mut |player_cards, _, _| player_totals.record(player_cards)
+        );
+
+        Ok(PlayerTotalsResolution {
+            outcomes,
+            player_totals: player_totals.to_player_totals()
+        })
+    }
+
+    /// Generates outcomes for doubling for less, where the player adds `extra` (at most
+    /// their original bet) to the bet, takes exactly one card and stands
+    pub fn generate_double_for_outcomes(&self, data: UserDataStateHolder, extra: f64) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        let mut outcomes = self.generate_double_for(&data, extra).map_err(|error| error.to_js())?;
+        outcomes.sanitise();
+        Ok(serde_wasm_bindgen::to_value(&outcomes)?)
+    }
+
+    fn generate_double_for(&self, data: &UserDataState, extra: f64) -> Result<ProbabilityValueOutcomes, SimulationError> {
+        // doubling is only possible on the first two cards, for more than nothing
+        // and for no more than the original bet
+        if data.current_cards.len() != 2 {
+            return Err(SimulationError::new(SimulationErrorKind::NotApplicable, "doubling is only possible on the first two cards"));
+        }
+        if !(extra > 0.0 && extra <= data.bet_size) {
+            let message = format!("doubling for {} must add more than nothing and no more than the bet of {}", extra, data.bet_size);
+            return Err(SimulationError::new(SimulationErrorKind::InvalidSettings, &message));
+        }
+
+        Ok(self.generate_outcomes(data, BlackJackAction::DOUBLE_FOR(extra / data.bet_size)))
+    }
+
+    /// Generates outcomes for hitting until the hand reaches the target total, including
+    /// the average number of cards the player draws before stopping
+    pub fn generate_hit_to_outcomes(&self, data: UserDataStateHolder, target: u8) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        let mut outcomes = self.generate_outcomes(&data, BlackJackAction::HIT_TO(target));
+        outcomes.sanitise();
+        Ok(serde_wasm_bindgen::to_value(&outcomes)?)
+    }
+
+    /// Computes the result of a player natural exactly rather than simulating it: it pushes
+    /// when the dealer also has a natural and otherwise wins 3:2. Errors if the player's
+    /// hand isn't a natural
+    pub fn natural_outcome(&self, data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        let outcome = natural_outcome_of(&data).map_err(|error| error.to_js())?;
+        Ok(serde_wasm_bindgen::to_value(&outcome)?)
+    }
+
+    /// Compares taking even money on a natural against an ace (a guaranteed win of the bet)
+    /// with declining it and standing on the natural, which pushes if the dealer also has one.
+    /// Errors unless the player has a natural and the dealer shows just an ace
+    pub fn even_money(&self, data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        let decision = self.generate_even_money(&data).map_err(|error| error.to_js())?;
+        Ok(serde_wasm_bindgen::to_value(&decision)?)
+    }
+
+    /// Streams each simulation's net result (in dollars) for the named action to `callback`
+    /// in batches of `batch_size`, so large runs never hold every result in memory at once.
+    /// Stops calling back after the first error the callback throws and returns it
+    pub fn stream_net_results(
+        &self,
+        data: UserDataStateHolder,
+        action: String,
+        batch_size: u32,
+        callback: &js_sys::Function
+    ) -> Result<(), JsValue> {
+        let data = parse_valid_state(data)?;
+        let action = parse_action(&action).map_err(|error| error.to_js())?;
+        check_at_least_one("batch_size", batch_size).map_err(|error| error.to_js())?;
+
+        let mut result = Ok(());
+        self.stream_net_results_with(&data, action, batch_size as usize, &mut |batch| {
+            if result.is_ok() {
+                let batch = js_sys::Float64Array::from(batch);
+                result = callback.call1(&JsValue::NULL, &batch).map(|_| ());
+            }
+        });
+        result
+    }
+
+    /// Simulates the named action, calling `callback` with the outcomes estimated so far every
+    /// `snapshot_every` simulations and once more at the end, so a UI can show the EV
+    /// converging and stop early by throwing from the callback. Returns the final outcomes
+    pub fn stream_outcome_snapshots(
+        &self,
+        data: UserDataStateHolder,
+        action: String,
+        snapshot_every: u32,
+        callback: &js_sys::Function
+    ) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+        let action = parse_action(&action).map_err(|error| error.to_js())?;
+        check_at_least_one("snapshot_every", snapshot_every).map_err(|error| error.to_js())?;
+
+        let mut result = Ok(());
+        let mut outcomes = self.generate_outcomes_with_snapshots(&data, action, &mut |_, _, _| {}, snapshot_every, &mut |snapshot| {
+            let mut snapshot = snapshot.clone();
+            snapshot.sanitise();
+            result = serde_wasm_bindgen::to_value(&snapshot)
+                .map_err(JsValue::from)
+                .and_then(|snapshot| callback.call1(&JsValue::NULL, &snapshot).map(|_| ()));
+            if result.is_ok() { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+        });
+        result?;
+        outcomes.sanitise();
+        Ok(serde_wasm_bindgen::to_value(&outcomes)?)
+    }
+
+    /// The outcome map (win, blackjack, loss, tie) for one labelled action, e.g. "stand"
+    pub fn action_outcomes_map(&self, data: UserDataStateHolder, action: String) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+        let action = parse_action(&action).map_err(|error| error.to_js())?;
+
+        let mut outcomes = self.generate_outcomes(&data, action);
+        outcomes.sanitise();
+        Ok(outcomes.outcomes_map())
+    }
+
+    /// Estimates how many simulations are needed before the best and second best actions can
+    /// be told apart at the given (one-sided) confidence, e.g. 0.95, from a pilot run of
+    /// SEPARATION_PILOT_SIMS. Errors unless the confidence is between 0.5 and 1
+    pub fn sims_to_separate_top_two(&self, data: UserDataStateHolder, confidence: f64) -> Result<u32, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        self.estimate_sims_to_separate_top_two(&data, confidence).map_err(|error| error.to_js())
+    }
+
+    fn estimate_sims_to_separate_top_two(&self, data: &UserDataState, confidence: f64) -> Result<u32, SimulationError> {
+        if !(0.5..1.0).contains(&confidence) {
+            let message = format!("the confidence must be at least 0.5 and below 1 but is {}", confidence);
+            return Err(SimulationError::new(SimulationErrorKind::InvalidSettings, &message));
+        }
+
+        let pilot = UserDataState { num_sims: SEPARATION_PILOT_SIMS, ..data.clone() };
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&pilot);
+        let mut applicable: Vec<_> = action_outcomes
+            .labelled_actions()
+            .into_iter()
+            .map(|(_, outcomes, _)| outcomes)
+            .filter(|outcomes| outcomes.applicable)
+            .collect();
+        applicable.sort_by(|a, b| b.estimated_value.total_cmp(&a.estimated_value));
+        let [best, second, ..] = applicable[..] else {
+            return Err(SimulationError::new(SimulationErrorKind::NotApplicable, "there aren't two actions to tell apart"));
+        };
+
+        // the difference's standard error shrinks with the square root of the simulations, so
+        // scale the pilot's variance until the gap is z standard errors wide
+        let gap = best.estimated_value - second.estimated_value;
+        let pilot_variance = [best, second]
+            .iter()
+            .map(|outcomes| outcomes.std_error.unwrap_or(0.0).powi(2))
+            .sum::<f64>() * SEPARATION_PILOT_SIMS as f64;
+        let z = normal_quantile(confidence);
+        let num_sims = (z * z * pilot_variance / (gap * gap)).ceil();
+
+        // a NaN (no gap and no variance) casts to 0 and an infinity (no gap) saturates
+        Ok((num_sims as u32).max(1))
+    }
+
+    /// How much (in dollars) following the recommended action is worth over "mimicking the
+    /// dealer", i.e. hitting until 17 regardless of the dealer's upcard
+    pub fn recommendation_edge(&self, data: UserDataStateHolder) -> Result<f64, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        Ok(finite_or_zero(self.generate_recommendation_edge(&data)))
+    }
+
+    fn generate_recommendation_edge(&self, data: &UserDataState) -> f64 {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(data);
+        let recommended_ev = action_outcomes.recommended_outcomes(TieBreak::FixedOrder).estimated_value;
+
+        let mimic_ev = self.generate_outcomes(data, BlackJackAction::HIT_TO(17)).estimated_value;
+        recommended_ev - mimic_ev
+    }
+
+    /// Plays the hand `hands_per_session` times at a flat bet with the recommended action, over
+    /// `num_sessions` sessions, and returns the distribution of each session's net result.
+    /// This shows the variance a session could have rather than just its EV
+    pub fn session_results(
+        &self,
+        data: UserDataStateHolder,
+        hands_per_session: u32,
+        num_sessions: u32
+    ) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+        let results = self
+            .generate_session_results(&data, hands_per_session, num_sessions)
+            .map_err(|error| error.to_js())?;
+
+        Ok(serde_wasm_bindgen::to_value(&results)?)
+    }
+
+    /// Plays `num_trajectories` sequences of `num_hands` hands of the recommended action from a
+    /// starting bankroll at a flat bet (the data's bet size), and returns the chance of going
+    /// broke along with the spread of final bankrolls. Bets shrink to what the bankroll can
+    /// cover once it's below the flat bet. Errors if there are no hands or trajectories, or
+    /// the bankroll isn't positive
+    pub fn bankroll_risk(
+        &self,
+        data: UserDataStateHolder,
+        starting_bankroll: f64,
+        num_hands: u32,
+        num_trajectories: u32
+    ) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+        let risk = self
+            .generate_bankroll_risk(&data, starting_bankroll, num_hands, num_trajectories)
+            .map_err(|error| error.to_js())?;
+
+        Ok(serde_wasm_bindgen::to_value(&risk)?)
+    }
+
+    /// Rather than simulating every hand of every trajectory, this simulates the hand
+    /// num_sims times and resamples the net results, which keeps long trajectories cheap
+    fn generate_bankroll_risk(
+        &self,
+        data: &UserDataState,
+        starting_bankroll: f64,
+        num_hands: u32,
+        num_trajectories: u32
+    ) -> Result<BankrollRisk, SimulationError> {
+        check_at_least_one("num_hands", num_hands)?;
+        check_at_least_one("num_trajectories", num_trajectories)?;
+        if !(starting_bankroll.is_finite() && starting_bankroll > 0.0) {
+            let message = format!("the starting bankroll must be positive but is {}", starting_bankroll);
+            return Err(SimulationError::new(SimulationErrorKind::InvalidSettings, &message));
+        }
+
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(data);
+        let label = action_outcomes.recommend_best_action(TieBreak::FixedOrder);
+        let action = parse_action(&label)?;
+
+        let stake = action_stake(&action);
+        let mut net_results = Vec::with_capacity(data.num_sims as usize);
+        let hand_data = UserDataState { importance_sampling: false, target_ci_width: None, ..data.clone() };
+        self.generate_outcomes_with(&hand_data, action, &mut |_, _, net_bets| net_results.push(net_bets));
+        if net_results.is_empty() {
+            return Err(SimulationError::new(SimulationErrorKind::DeckExhausted, "every simulation of the hand ran out of cards"));
+        }
+
+        // the bet is cut down so that losing the whole stake (e.g. a double) can't go below 0
+        let mut rng = SimRng::from_seed(data.seed);
+        let mut ruined = 0;
+        let mut final_bankrolls: Vec<f64> = (0..num_trajectories)
+            .map(|_| {
+                let mut bankroll = starting_bankroll;
+                for _ in 0..num_hands {
+                    let Some(index) = rng.next_index(net_results.len()) else { break };
+                    let bet = data.bet_size.min(bankroll / stake);
+                    bankroll = (bankroll + net_results[index] * bet).max(0.0);
+                    if bankroll < BROKE_BELOW {
+                        ruined += 1;
+                        return 0.0;
+                    }
+                }
+                bankroll
+            })
+            .collect();
+        final_bankrolls.sort_by(f64::total_cmp);
+
+        let percentile = |fraction: f64| final_bankrolls[((final_bankrolls.len() - 1) as f64 * fraction).round() as usize];
+        Ok(BankrollRisk {
+            action: label,
+            ruin_probability: ratio(ruined, num_trajectories),
+            median_final_bankroll: percentile(0.5),
+            p5_final_bankroll: percentile(0.05),
+            p95_final_bankroll: percentile(0.95)
+        })
+    }
+
+    /// The recommended action's EV and variance at each of the given bet sizes. EV scales
+    /// linearly and variance quadratically with the bet, so this only simulates once
+    pub fn bet_size_sweep(&self, data: UserDataStateHolder, bets: Vec<f64>) -> Result<JsValue, JsValue> {
+        let data = parse_valid_state(data)?;
+
+        Ok(serde_wasm_bindgen::to_value(&self.generate_bet_size_sweep(&data, &bets))?)
+    }
+
+    fn generate_bet_size_sweep(&self, data: &UserDataState, bets: &[f64]) -> BetSizeSweep {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(data);
+        let (label, outcomes) = action_outcomes.recommended_action(TieBreak::FixedOrder);
+
+        let unit_variance = outcomes.unit_variance();
+        let points = bets
+            .iter()
+            .map(|&bet| BetSweepPoint {
+                bet,
+                ev: finite_or_zero(outcomes.ev_at_bet(bet)),
+                variance: finite_or_zero(unit_variance * bet * bet)
+            })
+            .collect();
+
+        BetSizeSweep { action: label.to_string(), points }
+    }
+
+    fn generate_session_results(
+        &self,
+        data: &UserDataState,
+        hands_per_session: u32,
+        num_sessions: u32
+    ) -> Result<SessionResults, SimulationError> {
+        check_at_least_one("hands_per_session", hands_per_session)?;
+        check_at_least_one("num_sessions", num_sessions)?;
+        let num_hands = hands_per_session.checked_mul(num_sessions).ok_or_else(|| {
+            SimulationError::new(SimulationErrorKind::InvalidSettings, "there are too many hands over all the sessions to play")
+        })?;
+
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(data);
+        let label = action_outcomes.recommend_best_action(TieBreak::FixedOrder);
+        let action = parse_action(&label)?;
+
+        let mut session_counts = std::collections::BTreeMap::new();
+        let mut session_net = 0.0;
+        let mut hands_played = 0;
+        let mut sessions_played = 0;
+        let mut total_net = 0.0;
+        // every session has to be played out, so the hands can't stop early at a target width
+        let session_data = UserDataState { num_sims: num_hands, importance_sampling: false, target_ci_width: None, ..data.clone() };
+        self.generate_outcomes_with(&session_data, action, &mut |_, _, net_bets| {
+            session_net += net_bets;
+            hands_played += 1;
+            if hands_played == hands_per_session {
+                // only the histogram is rounded, e.g. a 3:2 natural still adds 1.5 to the mean
+                *session_counts.entry(session_net.round() as i64).or_insert(0u64) += 1;
+                total_net += session_net;
+                sessions_played += 1;
+                session_net = 0.0;
+                hands_played = 0;
+            }
+        });
+
+        // discarded simulations never reach the callback, so fewer sessions may finish
+        let mean_units = total_net / sessions_played as f64;
+        let buckets = session_counts
+            .into_iter()
+            .map(|(net_units, count)| SessionBucket { net_units, probability: ratio(count, sessions_played) })
+            .collect();
+
+        Ok(SessionResults {
+            action: label,
+            hands_per_session,
+            num_sessions: sessions_played,
+            mean: finite_or_zero(mean_units * data.bet_size),
+            buckets
+        })
+    }
+
+    /// Fills in every action for a valid BJ game state, marking actions that
+    /// make no sense for the hand as not applicable
+    fn populate_action_outcomes(&mut self, data: &UserDataState) {
+        self.populate_action_outcomes_with_progress(data, &mut |_| {}, &mut || false);
+    }
+
+    /// Like populate_action_outcomes, reporting overall progress through the simulations to
+    /// `on_progress` and stopping early once `should_cancel` returns true (see SimProgress).
+    /// Returns false if it was cancelled, leaving the outcomes incomplete
+    fn populate_action_outcomes_with_progress(
+        &mut self,
+        data: &UserDataState,
+        on_progress: &mut impl FnMut(f64),
+        should_cancel: &mut impl FnMut() -> bool
+    ) -> bool {
+        // every action is simulated from one seed, a fresh one unless it's set, so they share
+        // common random numbers (see SimRng::for_simulation)
+        let data = &data.clone().with_seed(data.seed.or_else(|| SimRng::Entropy.next_u64()));
+        // play_optimal is simulated too, though it isn't one of the actions recommended from
+        let total_sims = (self.labelled_actions().len() + 1) as u64 * data.num_sims as u64;
+        let mut progress = SimProgress::new(total_sims, on_progress, should_cancel);
+        // you would never risk busting a 21, so drawing actions are not offered
+        let target_total = data.rules.target_total;
+        let cannot_draw = (best_hand_total_to(&data.current_cards, target_total) == Some(target_total))
+            .then_some("drawing is not offered once the hand reaches the target total");
+        let cannot_split = cannot_draw.or((!can_split_hand(&data.current_cards))
+            .then_some("split requires a pair"));
+
+        // currently we "hit" 6 times but could bring this down to 3 - unsure if this would
+        // make it much faster however.
+        self.hit_once = self.generate_applicable_outcomes(data, BlackJackAction::HIT(1), cannot_draw, &mut progress);
+        self.hit_twice = self.generate_applicable_outcomes(data, BlackJackAction::HIT(2), cannot_draw, &mut progress);
+        self.hit_thrice = self.generate_applicable_outcomes(data, BlackJackAction::HIT(3), cannot_draw, &mut progress);
+
+        // the honest value of "I'll hit and see": one card, then the best of hit/stand from there
+        let cannot_play_optimally = cannot_draw
+            .or((target_total != 21).then_some("optimal play is only solved for a target total of 21"));
+        self.hit_once_then_optimal = self.generate_applicable_outcomes(
+            data,
+            BlackJackAction::HIT_THEN_OPTIMAL(1),
+            cannot_play_optimally,
+            &mut progress
+        );
+        // and the best that can be done from the hand, hitting or standing at every step
+        self.play_optimal = self.generate_applicable_outcomes(
+            data,
+            BlackJackAction::HIT_THEN_OPTIMAL(0),
+            cannot_play_optimally,
+            &mut progress
+        );
+
+        // a liberal rule: hit once, then double on the three-card hand
+        let cannot_hit_then_double = cannot_draw
+            .or((!data.rules.double_after_hit).then_some("doubling after hitting is not allowed"))
+            .or((data.current_cards.len() != 2).then_some("hit then double starts from the first two cards"));
+        self.hit_then_double = self.generate_applicable_outcomes(
+            data,
+            BlackJackAction::HIT_THEN_DOUBLE,
+            cannot_hit_then_double,
+            &mut progress
+        );
+
+        let cannot_double = cannot_draw
+            .or((data.current_cards.len() != 2).then_some("doubling is only offered on the first two cards"));
+        self.double_down = self.generate_applicable_outcomes(data, BlackJackAction::DOUBLE, cannot_double, &mut progress);
+
+        self.stand = self.generate_applicable_outcomes(data, BlackJackAction::STAND, None, &mut progress);
+
+        // late surrender, which doesn't need any draws so is also offered on 21
+        let cannot_surrender = (data.current_cards.len() != 2)
+            .then_some("surrender is only offered on the first two cards");
+        self.surrender = self.generate_applicable_outcomes(data, BlackJackAction::SURRENDER, cannot_surrender, &mut progress);
+        self.insurance = self.generate_insurance_outcome(data);
+
+        self.split_hit_once = self.generate_applicable_outcomes(data, BlackJackAction::SPLIT(1), cannot_split, &mut progress);
+        // split aces get exactly one card each
+        let cannot_hit_split_hand = cannot_split
+            .or((data.current_cards[0] == Card::Ace).then_some("split aces receive one card each"));
+        self.split_hit_twice = self.generate_applicable_outcomes(data, BlackJackAction::SPLIT(2), cannot_hit_split_hand, &mut progress);
+        self.split_hit_thrice = self.generate_applicable_outcomes(data, BlackJackAction::SPLIT(3), cannot_hit_split_hand, &mut progress);
+
+        // guard against NaN/Inf or out of range values before anything is serialized
+        self.all_outcomes_mut().into_iter().for_each(|outcomes| outcomes.sanitise());
+        self.note = self.recommendation_note();
+        !progress.cancelled
+    }
+
+    /// Converts probabilities to the requested output units, this is done just before
+    /// serializing since everything else works in fractions
+    fn apply_output_units(&mut self) {
+        if self.as_percent {
+            self.all_outcomes_mut().into_iter().for_each(|outcomes| outcomes.scale_probabilities(100.0));
+        }
+    }
+
+    /// Lists every action's outcomes, in the same order as all_outcomes_mut
+    fn all_outcomes(&self) -> [&ProbabilityValueOutcomes; 13] {
+        [
+            &self.stand,
+            &self.hit_once,
+            &self.hit_twice,
+            &self.hit_thrice,
+            &self.hit_once_then_optimal,
+            &self.play_optimal,
+            &self.hit_then_double,
+            &self.double_down,
+            &self.surrender,
+            &self.insurance,
+            &self.split_hit_once,
+            &self.split_hit_twice,
+            &self.split_hit_thrice,
+        ]
+    }
+
+    /// Lists every action's outcomes so they can be updated together
+    fn all_outcomes_mut(&mut self) -> [&mut ProbabilityValueOutcomes; 13] {
+        [
+            &mut self.stand,
+            &mut self.hit_once,
+            &mut self.hit_twice,
+            &mut self.hit_thrice,
+            &mut self.hit_once_then_optimal,
+            &mut self.play_optimal,
+            &mut self.hit_then_double,
+            &mut self.double_down,
+            &mut self.surrender,
+            &mut self.insurance,
+            &mut self.split_hit_once,
+            &mut self.split_hit_twice,
+            &mut self.split_hit_thrice,
+        ]
+    }
+
+    /// Generates outcomes for an action only if it can be taken, otherwise marks it as not
+    /// applicable with the reason it can't be taken
+    fn generate_applicable_outcomes(
+        &self,
+        data: &UserDataState,
+        action: BlackJackAction,
+        unavailable_reason: Option<&str>,
+        progress: &mut SimProgress
+    ) -> ProbabilityValueOutcomes {
+        let started = progress.completed_sims;
+        let unavailable_reason = unavailable_reason
+            .or(progress.cancelled.then_some("the simulation was cancelled"));
+        let outcomes = match unavailable_reason {
+            None if data.exact_enumeration && let Some(outcomes) = exact_shallow_outcomes(data, &action) => outcomes,
+            // snapshots are cheap next to a hundredth of the simulations
+            None => self.generate_outcomes_with_snapshots(
+                data,
+                action,
+                &mut |_, _, _| {},
+                (data.num_sims / 100).clamp(1, CANCEL_CHECK_SIMS),
+                &mut |snapshot| {
+                    progress.advance_to(started + (snapshot.num_sims + snapshot.discarded_sims) as u64);
+                    if progress.poll_cancelled() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+                }
+            ),
+            Some(reason) => ProbabilityValueOutcomes::not_applicable(reason)
+        };
+        // an action that isn't simulated is done straight away
+        progress.advance_to(started + data.num_sims as u64);
+        outcomes
+    }
+
+    /// Simulates an action, passing the net results to `on_batch` in batches of at most `batch_size`
+    fn stream_net_results_with(
+        &self,
+        data: &UserDataState,
+        action: BlackJackAction,
+        batch_size: usize,
+        on_batch: &mut impl FnMut(&[f64])
+    ) {
+        let mut batch = Vec::with_capacity(batch_size);
+        let data = UserDataState { importance_sampling: false, target_ci_width: None, ..data.clone() };
+        self.generate_outcomes_with(&data, action, &mut |_, _, net_bets| {
+            batch.push(net_bets * data.bet_size);
+            if batch.len() == batch_size {
+                on_batch(&batch);
+                batch.clear();
+            }
+        });
+
+        if !batch.is_empty() {
+            on_batch(&batch);
+        }
+    }
+
+    /// Simulates the dealer's hole card to value insurance, a side bet of half the main bet
+    /// that pays 2:1 when the dealer has a natural. Win is the chance the hole card is ten-valued
+    /// given the cards already seen, and the EV is in dollars of the main bet's size. Only
+    /// offered when the dealer shows just an ace, otherwise every value is zero
+    fn generate_insurance_outcome(&self, data: &UserDataState) -> ProbabilityValueOutcomes {
+        if data.dealer_card != [Card::Ace] {
+            return ProbabilityValueOutcomes {
+                win: 0.0,
+                loss: 0.0,
+                stake: 0.0,
+                ..ProbabilityValueOutcomes::not_applicable("insurance is only offered against an ace")
+            };
+        }
+
+        let deck = remaining_deck(data);
+        let mut rng = SimRng::from_seed(data.seed);
+        let hole_tens = (0..data.num_sims)
+            .map(|_| match data.rules.infinite_deck {
+                true => draw_from_infinite_deck(&mut rng),
+                false => deck.random_card_using(&mut rng)
+            })
+            .filter(|card| card.as_ref().is_some_and(|card| card.rank_index() == Some(9)))
+            .count();
+        let win = ratio(hole_tens as u64, data.num_sims);
+
+        let mut outcomes = ProbabilityValueOutcomes {
+            win,
+            loss: 1.0 - win,
+            stake: INSURANCE_STAKE,
+            // the side bet wins twice its stake or loses it
+            ev_per_bet: INSURANCE_STAKE * (2.0 * win - (1.0 - win)),
+            ..ProbabilityValueOutcomes::new()
+        };
+        outcomes.estimated_value = outcomes.ev_at_bet(data.bet_size);
+        outcomes
+    }
+
+    /// The dealer's chance of a natural is simulated from the hole card, like insurance
+    fn generate_even_money(&self, data: &UserDataState) -> Result<EvenMoneyDecision, SimulationError> {
+        if !is_natural(&data.current_cards) || data.dealer_card != [Card::Ace] {
+            return Err(SimulationError::new(SimulationErrorKind::NotApplicable, "even money is only offered on a natural against a dealer ace"));
+        }
+
+        let dealer_natural_probability = self.generate_insurance_outcome(data).win;
+        let even_money_ev = data.bet_size;
+        let decline_ev = (1.0 - dealer_natural_probability) * data.rules.blackjack_payout * data.bet_size;
+        Ok(EvenMoneyDecision {
+            dealer_natural_probability,
+            even_money_ev,
+            decline_ev,
+            take_even_money: even_money_ev > decline_ev
+        })
+    }
+
+    /// Generates probabilities and EVs for a single action
+    fn generate_outcomes(&self, data: &UserDataState, action: BlackJackAction) -> ProbabilityValueOutcomes {
+        if data.exact_enumeration && let Some(outcomes) = exact_shallow_outcomes(data, &action) { return outcomes; }
+        self.generate_outcomes_with(data, action, &mut |_, _, _| {})
+    }
+
+    /// Generates probabilities and EVs for a single action, calling `observe_hands` with the
+    /// player's final hand (the first one after a split), the dealer's final total (None if
+    /// they bust) and the net result in bets over all the player's hands after every simulation
+    fn generate_outcomes_with(
+        &self,
+        data: &UserDataState,
+        action: BlackJackAction,
+        observe_hands: &mut impl FnMut(&[Card], Option<u8>, f64)
+    ) -> ProbabilityValueOutcomes {
+        self.generate_outcomes_with_snapshots(data, action, observe_hands, 0, &mut |_| ControlFlow::Continue(()))
+    }
+
+    /// Generates probabilities and EVs for a single action like `generate_outcomes_with`,
+    /// also calling `on_snapshot` with the estimate so far every `snapshot_every` simulations
+    /// (never if it's 0) and after the last one, so the final snapshot is the full result.
+    /// Breaking from `on_snapshot` stops early, returning the estimate from the simulations run
+    fn generate_outcomes_with_snapshots(
+        &self,
+        data: &UserDataState,
+        action: BlackJackAction,
+        observe_hands: &mut impl FnMut(&[Card], Option<u8>, f64),
+        snapshot_every: u32,
+        on_snapshot: &mut impl FnMut(&ProbabilityValueOutcomes) -> ControlFlow<()>
+    ) -> ProbabilityValueOutcomes {
+        self.generate_outcomes_with_solver(data, action, observe_hands, snapshot_every, on_snapshot, true)
+    }
+
+    /// generate_outcomes_with_snapshots, where `memoise_solver` false has the optimal play
+    /// solver work out every decision afresh (see HitStandSolver::without_memo)
+    fn generate_outcomes_with_solver(
+        &self,
+        data: &UserDataState,
+        action: BlackJackAction,
+        observe_hands: &mut impl FnMut(&[Card], Option<u8>, f64),
+        snapshot_every: u32,
+        on_snapshot: &mut impl FnMut(&ProbabilityValueOutcomes) -> ControlFlow<()>,
+        memoise_solver: bool
+    ) -> ProbabilityValueOutcomes {
+        let target_total = data.rules.target_total;
+        let mut tally = OutcomeTally::new(&data.rules);
+
+        let deck = remaining_deck(data);
+        check_deck_composition(&deck, data);
+
+        // the dealer is dealt a hole card unless the analysis fixes their total, their cards
+        // are already known or they take no hole card (ENHC), and peeks at it for a natural
+        // before the player acts
+        let european = data.rules.european_no_hole_card;
+        let deal_hole_card = data.fixed_dealer_total.is_none() && data.dealer_card.len() == 1 && !european;
+
+        // only optimal play and doubling after a split need the (comparatively expensive)
+        // solver, which knows the dealer has no natural whenever the player gets to act after
+        // a peek. It works from card counts, so the most decks there can be stands in for an
+        // infinite deck. It's only solved for a target total of 21, so split hands don't double
+        // in other games (and optimal play isn't offered in them)
+        let infinite_deck = data.rules.infinite_deck;
+        let needs_solver = target_total == 21 && match action {
+            BlackJackAction::HIT_THEN_OPTIMAL(_) => true,
+            BlackJackAction::SPLIT(_) => data.rules.double_after_split,
+            _ => false
+        };
+        let solver = needs_solver.then(|| {
+            let solver = HitStandSolver::new(
+                &if infinite_deck { Deck::new(&u8::MAX) } else { deck.clone() },
+                &data.dealer_card,
+                deal_hole_card,
+                &data.rules
+            );
+            if memoise_solver { solver } else { solver.without_memo() }
+        });
+        let should_hit = |cards: &[Card]| solver
+            .as_ref()
+            .is_some_and(|solver| solver.should_hit(cards));
+        let should_double = |cards: &[Card]| solver
+            .as_ref()
+            .is_some_and(|solver| data.rules.double_after_split && solver.should_double(cards));
+        // the importance sampling proposal is only worked out for the normal game, drawing
+        // from a finite deck
+        let normal_dealer = target_total == 21 && !data.rules.dealer_hits_soft_17 && data.rules.dealer_stand_value == 17;
+        let dealer_tilt = (data.importance_sampling && normal_dealer && !infinite_deck)
+            .then(|| DealerDrawTilt::new(&deck));
+
+        let mut sims_run = 0;
+        let mut current_deck = Deck { cards: Vec::with_capacity(deck.cards.len()) };
+        for sim in 1..=data.num_sims {
+            let (mut rng, mut player_rng) = SimRng::for_simulation(data.seed, sim);
+            // an infinite deck is never drawn from, so there's nothing to copy
+            if !infinite_deck { current_deck.refill_from(&deck); }
+            // nobody else's cards change what's left of an infinite deck
+            let mut cards_drawn = match infinite_deck {
+                true => 0,
+                false => deal_other_seats(&mut current_deck, data.num_other_players, &mut rng)
+            };
+            let mut deck_ran_out = false;
+            let draw_card = &mut |rng: &mut SimRng| {
+                let card = if infinite_deck {
+                    draw_from_infinite_deck(rng)
+                } else {
+                    current_deck.take_random_card_using(rng)
+                };
+                match card {
+                    Some(_) => cards_drawn += 1,
+                    None => deck_ran_out = true
+                }
+                card
+            };
+
+            let mut dealer_cards = data.dealer_card.clone();
+            if deal_hole_card { draw_into(&mut dealer_cards, &mut || draw_card(&mut rng)); }
+
+            // a dealer natural ends the round before the player acts, losing only the original
+            // bet (nothing has been doubled, split or surrendered yet)
+            let dealer_natural = target_total == 21 && is_natural(&dealer_cards);
+            let hands = if dealer_natural {
+                vec![(data.current_cards.clone(), 1.0)]
+            } else {
+                handle_player_hands(
+                    &data.current_cards,
+                    &action,
+                    &mut || draw_card(&mut player_rng).inspect(|_| tally.player_draws += 1),
+                    &should_hit,
+                    &should_double,
+                    &data.rules
+                )
+            };
+
+            // in worst-case analysis the dealer doesn't draw, they just finish on the fixed total
+            let mut weight = 1.0;
+            let dealer_total = match (data.fixed_dealer_total, &dealer_tilt) {
+                (Some(total), _) => Some(total),
+                (None, Some(dealer_tilt)) => {
+                    let player_total = best_hand_total(&hands[0].0);
+                    while dealer_should_draw(&dealer_cards) {
+                        match dealer_tilt.draw(&mut current_deck, &dealer_cards, player_total, &mut rng) {
+                            Some((card, card_weight)) => {
+                                cards_drawn += 1;
+                                weight *= card_weight;
+                                dealer_cards.push(card);
+                            }
+                            None => {
+                                deck_ran_out = true;
+                                break;
+                            }
+                        }
+                    }
+                    best_hand_total(&dealer_cards)
+                }
+                (None, None) => {
+                    handle_dealer_action(
+                        &mut dealer_cards,
+                        &mut || draw_card(&mut rng),
+                        &data.rules
+                    );
+                    best_hand_total_to(&dealer_cards, target_total)
+                }
+            };
+
+            // without a hole card the dealer only makes their natural after the player has acted,
+            // and it wins everything the player has bet by then
+            let dealer_natural = dealer_natural || (european && target_total == 21 && is_natural(&dealer_cards));
+
+            debug_assert!(infinite_deck || deck.cards.len() == current_deck.cards.len() + cards_drawn,
+                          "the deck must shrink by exactly the number of cards drawn");
+            if deck_ran_out && data.on_deck_empty == DeckEmptyPolicy::DiscardSim {
+                tally.discarded += 1;
+            } else {
+                let results: Vec<_> = hands.iter()
+                    .map(|(player_cards, stake)| {
+                        let total = best_hand_total_to(player_cards, target_total);
+                        let natural = target_total == 21 && is_player_natural(&data.current_cards, player_cards, &action);
+                        HandResult {
+                            outcome: match action {
+                                BlackJackAction::SURRENDER if !dealer_natural => GameOutcome::LOSS, // of the surrendered half
+                                _ => evaluate_hands(player_cards, natural, dealer_total, dealer_natural, &data.rules)
+                            },
+                            total,
+                            stake: *stake,
+                            natural: natural && !dealer_natural
+                        }
+                    })
+                    .collect();
+                let net = tally.record(&results, dealer_total, weight);
+                observe_hands(&hands[0].0, dealer_total, net);
+            }
+
+            sims_run = sim;
+            let converged = data.target_ci_width.is_some_and(|target| {
+                sim.is_multiple_of(CONVERGENCE_CHECK_SIMS) && tally.ev_std_error()
+                    .is_some_and(|std_error| 1.96 * std_error * data.bet_size.abs() < target)
+            });
+            let take_snapshot = sim == data.num_sims || converged || (snapshot_every > 0 && sim.is_multiple_of(snapshot_every));
+            if take_snapshot && on_snapshot(&tally.to_outcomes(&action, data.bet_size)).is_break() { break; }
+            if converged { break; }
+        }
+
+        debug_assert_eq!(sims_run as u64, tally.counted + tally.discarded,
+                         "every simulation must end in exactly one outcome or be discarded");
+        tally.to_outcomes(&action, data.bet_size)
+    }
+}
+
+/// How one of the player's hands finished in a simulation, with its total (None for a bust)
+/// and the number of bets it had at risk
+struct HandResult {
+    outcome: GameOutcome,
+    total: Option<u8>,
+    stake: f64,
+    natural: bool
+}
+
+/// How often a simulation with a target_ci_width checks whether its EV has converged
+const CONVERGENCE_CHECK_SIMS: u32 = 2_000;
+
+/// The most simulations run between checks for cancellation, few enough to stop quickly
+/// and enough that checking (a call into JS) costs nothing next to simulating
+const CANCEL_CHECK_SIMS: u32 = 4096;
+
+/// Overall progress through the simulations for every action, reporting the fraction
+/// completed only when it passes another percent so JS isn't called back on every simulation.
+/// Once cancelled it stays cancelled
+struct SimProgress<'a> {
+    total_sims: u64,
+    completed_sims: u64,
+    reported_percent: u64,
+    cancelled: bool,
+    on_progress: &'a mut dyn FnMut(f64),
+    should_cancel: &'a mut dyn FnMut() -> bool
+}
+
+impl<'a> SimProgress<'a> {
+    fn new(total_sims: u64, on_progress: &'a mut dyn FnMut(f64), should_cancel: &'a mut dyn FnMut() -> bool) -> Self {
+        SimProgress { total_sims, completed_sims: 0, reported_percent: 0, cancelled: false, on_progress, should_cancel }
+    }
+
+    fn poll_cancelled(&mut self) -> bool {
+        self.cancelled = self.cancelled || (self.should_cancel)();
+        self.cancelled
+    }
+
+    /// Moves the progress on, which stops once cancelled since the rest is never simulated
+    fn advance_to(&mut self, completed_sims: u64) {
+        if self.cancelled { return; }
+        self.completed_sims = completed_sims.min(self.total_sims);
+        if self.total_sims == 0 { return; }
+
+        let percent = self.completed_sims * 100 / self.total_sims;
+        if percent > self.reported_percent {
+            self.reported_percent = percent;
+            (self.on_progress)(self.completed_sims as f64 / self.total_sims as f64);
+        }
+    }
+}
+
+/// Accumulates the results of the simulations run so far for one action. Outcomes are
+/// weighted sums, every simulation has weight 1 unless importance sampling
+struct OutcomeTally {
+    wins: f64,
+    losses: f64,
+    ties: f64,
+    blackjacks: f64,
+    win_count: u32,
+    loss_count: u32,
+    tie_count: u32,
+    net_bets: f64,
+    net_bets_squared: f64,
+    player_totals: Vec<f64>,
+    dealer_totals: Vec<f64>,
+    player_draws: u64,
+    counted: u64,
+    discarded: u64,
+    blackjack_payout: f64
+}
+
+impl OutcomeTally {
+    fn new(rules: &RulesConfig) -> OutcomeTally {
+        let target_total = rules.target_total;
+        OutcomeTally {
+            wins: 0.0,
+            losses: 0.0,
+            ties: 0.0,
+            blackjacks: 0.0,
+            win_count: 0,
+            loss_count: 0,
+            tie_count: 0,
+            net_bets: 0.0,
+            net_bets_squared: 0.0,
+            player_totals: vec![0.0; target_total as usize + 1],
+            dealer_totals: vec![0.0; target_total as usize + 1],
+            player_draws: 0,
+            counted: 0,
+            discarded: 0,
+            blackjack_payout: rules.blackjack_payout
+        }
+    }
+
+    /// Records one simulation's hands against the dealer's final total (None for a bust),
+    /// returning the net result in bets over all of them. The win/loss/tie probabilities are
+    /// averaged over the hands, so each of two split hands counts for half a simulation
+    fn record(&mut self, hands: &[HandResult], dealer_total: Option<u8>, weight: f64) -> f64 {
+        let hand_weight = weight / hands.len() as f64;
+        let mut net = 0.0;
+        for hand in hands {
+            net += match hand.outcome {
+                GameOutcome::WIN if hand.natural => {
+                    self.wins += hand_weight;
+                    self.win_count += 1;
+                    self.blackjacks += hand_weight;
+                    hand.stake * self.blackjack_payout
+                }
+                GameOutcome::WIN => { self.wins += hand_weight; self.win_count += 1; hand.stake }
+                GameOutcome::LOSS => { self.losses += hand_weight; self.loss_count += 1; -hand.stake }
+                GameOutcome::TIE => { self.ties += hand_weight; self.tie_count += 1; 0.0 }
+            };
+            self.player_totals[hand.total.unwrap_or(0) as usize] += hand_weight;
+        }
+        self.counted += 1;
+        self.net_bets += weight * net;
+        self.net_bets_squared += (weight * net).powi(2);
+        self.dealer_totals[dealer_total.unwrap_or(0) as usize] += weight;
+        net
+    }
+
+    /// Sample variance of the net result in bets, which needs at least two simulations (its
+    /// n - 1 denominator would otherwise be 0)
+    fn net_variance(&self) -> Option<f64> {
+        if self.counted < 2 { return None; }
+
+        let n = self.counted as f64;
+        let mean = self.net_bets / n;
+        Some(((self.net_bets_squared - n * mean * mean) / (n - 1.0)).max(0.0))
+    }
+
+    /// Standard error of the EV per bet from the sample variance
+    fn ev_std_error(&self) -> Option<f64> {
+        self.net_variance().map(|variance| (variance / self.counted as f64).sqrt())
+    }
+
+    fn to_outcomes(&self, action: &BlackJackAction, bet_size: f64) -> ProbabilityValueOutcomes {
+        // discarded simulations don't count towards any of the results. Importance sampled
+        // results are averaged over the simulations rather than normalised by the total
+        // weight, since it's the plain average whose variance the sampling reduces
+        let counted_sims = self.counted as f64;
+        let win_probability = weighted_ratio(self.wins, counted_sims);
+        let loss_probability = weighted_ratio(self.losses, counted_sims);
+        let tie_probability = weighted_ratio(self.ties, counted_sims);
+        let (modal_player_total, modal_player_total_frequency) = modal_total(&self.player_totals, counted_sims);
+        let (modal_dealer_total, modal_dealer_total_frequency) = modal_total(&self.dealer_totals, counted_sims);
+        let mut outcomes = ProbabilityValueOutcomes {
+            estimated_value: 0.0,
+            win: win_probability,
+            loss: loss_probability,
+            tie: tie_probability,
+            blackjack: weighted_ratio(self.blackjacks, counted_sims),
+            bust: weighted_ratio(self.player_totals[0], counted_sims), // busts are totalled as 0
+            applicable: true,
+            average_cards_drawn: ratio(self.player_draws, (self.counted + self.discarded) as u32),
+            stake: action_stake(action),
+            ev_per_bet: weighted_ratio(self.net_bets, counted_sims),
+            blackjack_payout: self.blackjack_payout,
+            std_error: self.ev_std_error().map(|std_error| std_error * bet_size.abs()),
+            win_std_error: binomial_std_error(win_probability, counted_sims),
+            loss_std_error: binomial_std_error(loss_probability, counted_sims),
+            wins: self.win_count,
+            losses: self.loss_count,
+            ties: self.tie_count,
+            num_sims: self.counted as u32,
+            discarded_sims: self.discarded as u32,
+            modal_player_total,
+            modal_player_total_frequency,
+            modal_dealer_total,
+            modal_dealer_total_frequency,
+            exact: false,
+            net_variance: self.net_variance().unwrap_or(0.0),
+            reason: None
+        };
+        outcomes.estimated_value = outcomes.ev_at_bet(bet_size);
+        outcomes
+    }
+}
+
+impl ActionOutcomes {
+    /// Generates every action's outcomes like generate_all_action_outcomes, for native callers
+    /// (e.g. the command line) that have no JS to pass errors to
+    pub fn simulate(data: UserDataStateHolder) -> Result<ActionOutcomes, String> {
+        let data = parse_state(data).map_err(|error| error.to_string())?;
+
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&data);
+        Ok(action_outcomes)
+    }
+
+    /// Lists each action with its name and the number of bets it puts at risk
+    fn labelled_actions(&self) -> [(&'static str, &ProbabilityValueOutcomes, f64); 11] {
+        [
+            ("stand", &self.stand, 1.0),
+            ("hit_once", &self.hit_once, 1.0),
+            ("hit_twice", &self.hit_twice, 1.0),
+            ("hit_thrice", &self.hit_thrice, 1.0),
+            ("hit_once_then_optimal", &self.hit_once_then_optimal, 1.0),
+            ("hit_then_double", &self.hit_then_double, 2.0),
+            ("double_down", &self.double_down, 2.0),
+            ("surrender", &self.surrender, 0.5),
+            ("split_hit_once", &self.split_hit_once, 2.0),
+            ("split_hit_twice", &self.split_hit_twice, 2.0),
+            ("split_hit_thrice", &self.split_hit_thrice, 2.0),
+        ]
+    }
+
+    /// Returns the name of the applicable action with the highest EV, using the given
+    /// policy to choose between actions whose EVs are equal up to floating-point noise
+    pub fn recommend_best_action(&self, tie_break: TieBreak) -> String {
+        self.recommended_action(tie_break).0.to_string()
+    }
+
+    /// Returns true if every action the player can take loses money on average
+    pub fn all_actions_negative(&self) -> bool {
+        self.labelled_actions()
+            .into_iter()
+            .filter(|(_, outcomes, _)| outcomes.applicable)
+            .all(|(_, outcomes, _)| outcomes.estimated_value < 0.0)
+    }
+
+    /// Compares the recommendation after populate_action_outcomes with basic strategy
+    fn strategy_comparison(&self, data: &UserDataState) -> StrategyComparison {
+        let simulated_action = self.best_action();
+        let basic_strategy_action = basic_strategy_action(data);
+        let simulated_move = match simulated_action.as_str() {
+            "stand" => "stand",
+            "double_down" => "double",
+            "surrender" => "surrender", // the tables don't include surrendering
+            label if label.starts_with("split") => "split",
+            _ => "hit" // hitting then doubling is a hit first
+        };
+        StrategyComparison {
+            agrees: simulated_move == basic_strategy_action,
+            simulated_action,
+            basic_strategy_action
+        }
+    }
+
+    /// A note to show alongside the recommendation, currently only when the best action
+    /// still loses money and the player should be minimising their losses
+    fn recommendation_note(&self) -> Option<String> {
+        self.all_actions_negative().then(|| {
+            "Every action loses money on average here, so the recommendation only minimises \
+            the loss. Surrender if it's offered".to_string()
+        })
+    }
+
+    /// Checks every applicable action's outcomes are finite, with probabilities that sum to
+    /// one and no more blackjacks than wins, naming the first action that isn't
+    fn check_outcomes(&self) -> Result<(), InvalidOutcomesError> {
+        for (label, outcomes, _) in self.labelled_actions() {
+            if !outcomes.applicable { continue; }
+
+            let values = [
+                outcomes.estimated_value, outcomes.ev_per_bet, outcomes.win, outcomes.loss, outcomes.tie,
+                outcomes.blackjack, outcomes.average_cards_drawn, outcomes.stake
+            ];
+            if !values.iter().all(|value| value.is_finite()) {
+                return Err(InvalidOutcomesError::NotFinite(label));
+            }
+
+            let sum = outcomes.win + outcomes.loss + outcomes.tie;
+            if (sum - 1.0).abs() > PROBABILITY_SUM_TOLERANCE {
+                return Err(InvalidOutcomesError::ProbabilitiesDontSumToOne(label, sum));
+            }
+            if outcomes.blackjack > outcomes.win {
+                return Err(InvalidOutcomesError::BlackjackExceedsWins(label));
+            }
+        }
+        Ok(())
+    }
+
+    /// The outcomes of the action `recommend_best_action` would choose
+    fn recommended_outcomes(&self, tie_break: TieBreak) -> &ProbabilityValueOutcomes {
+        self.recommended_action(tie_break).1
+    }
+
+    fn recommended_action(&self, tie_break: TieBreak) -> (&'static str, &ProbabilityValueOutcomes) {
+        let applicable: Vec<_> = self.labelled_actions()
+            .into_iter()
+            .filter(|(_, outcomes, _)| outcomes.applicable)
+            .collect();
+
+        let best_ev = applicable
+            .iter()
+            .map(|(_, outcomes, _)| outcomes.estimated_value)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let tolerance = EV_TIE_TOLERANCE * best_ev.abs().max(1.0);
+
+        let mut candidates = applicable
+            .into_iter()
+            .filter(|(_, outcomes, _)| best_ev - outcomes.estimated_value <= tolerance);
+
+        // min_by keeps the first of equal elements, so the fixed order is the final tie-break
+        let best = match tie_break {
+            TieBreak::FixedOrder => candidates.next(),
+            TieBreak::LowerVariance => candidates.min_by(|(_, a, _), (_, b, _)| {
+                a.unit_variance().total_cmp(&b.unit_variance())
+            }),
+            TieBreak::LowerStake => candidates.min_by(|(_, _, a), (_, _, b)| a.total_cmp(b)),
+        };
+
+        match best {
+            Some((name, outcomes, _)) => (name, outcomes),
+            None => ("stand", &self.stand) // standing is always possible
+        }
+    }
+}
+
+/// Builds the shoe with the known cards in the dealer/player hands removed
+fn remaining_deck(data: &UserDataState) -> Deck {
+    let mut deck = data.full_shoe();
+    data.current_cards.iter().for_each(|card| deck.remove_card_from_deck(card));
+    data.dealer_card.iter().for_each(|card| deck.remove_card_from_deck(card));
+    data.seen_cards.iter().for_each(|card| deck.remove_card_from_deck(card));
+    deck.apply_count_bias(data.count_bias);
+    deck
+}
+
+/// Checks that every known card was actually removed from the shoe, i.e. no card count
+/// would have gone negative. Like the other invariant checks, this compiles out in release.
+/// An infinite deck can have more of a card out than any shoe, and the shoe isn't drawn from
+fn check_deck_composition(deck: &Deck, data: &UserDataState) {
+    if data.rules.infinite_deck { return; }
+    let known_cards = data.current_cards.len() + data.dealer_card.len() + data.seen_cards.len();
+    debug_assert_eq!(data.full_shoe().cards.len(), deck.cards.len() + known_cards,
+                     "known cards must all come out of the shoe");
+}
+
+/// The fewest cards the other seats leave in the shoe, enough for the player and dealer to
+/// finish nearly any round. Seats go without cards rather than take the shoe below it
+const OTHER_SEATS_RESERVE: usize = 16;
+
+/// Deals two cards to each of the other players from the shoe, returning how many were dealt
+fn deal_other_seats(deck: &mut Deck, num_other_players: u8, rng: &mut SimRng) -> usize {
+    let seat_cards = (2 * num_other_players as usize).min(deck.cards.len().saturating_sub(OTHER_SEATS_RESERVE));
+    for _ in 0..seat_cards {
+        deck.take_random_card_using(rng);
+    }
+    seat_cards
+}
+
+/// action_for_label, erroring with the name that isn't an action
+fn parse_action(label: &str) -> Result<BlackJackAction, SimulationError> {
+    action_for_label(label).ok_or_else(|| {
+        SimulationError::new(SimulationErrorKind::UnknownAction, &format!("{:?} isn't the name of an action", label))
+    })
+}
+
+/// Errors unless a count setting (e.g. a batch size) is at least one
+fn check_at_least_one(name: &str, value: u32) -> Result<(), SimulationError> {
+    if value > 0 { return Ok(()); }
+    Err(SimulationError::new(SimulationErrorKind::InvalidSettings, &format!("{} must be at least 1", name)))
+}
+
+/// Maps an action's output name (as used by `labelled_actions`) back to the action
+fn action_for_label(label: &str) -> Option<BlackJackAction> {
+    match label {
+        "stand" => Some(BlackJackAction::STAND),
+        "hit_once" => Some(BlackJackAction::HIT(1)),
+        "hit_twice" => Some(BlackJackAction::HIT(2)),
+        "hit_thrice" => Some(BlackJackAction::HIT(3)),
+        "hit_once_then_optimal" => Some(BlackJackAction::HIT_THEN_OPTIMAL(1)),
+        "play_optimal" => Some(BlackJackAction::HIT_THEN_OPTIMAL(0)),
+        "hit_then_double" => Some(BlackJackAction::HIT_THEN_DOUBLE),
+        "double_down" => Some(BlackJackAction::DOUBLE),
+        "surrender" => Some(BlackJackAction::SURRENDER),
+        "split_hit_once" => Some(BlackJackAction::SPLIT(1)),
+        "split_hit_twice" => Some(BlackJackAction::SPLIT(2)),
+        "split_hit_thrice" => Some(BlackJackAction::SPLIT(3)),
+        _ => None
+    }
+}
+
+/// The number of bets the player has at risk after taking an action
+fn action_stake(action: &BlackJackAction) -> f64 {
+    match action {
+        BlackJackAction::DOUBLE_FOR(extra) => 1.0 + extra,
+        BlackJackAction::DOUBLE | BlackJackAction::HIT_THEN_DOUBLE | BlackJackAction::SPLIT(_) => 2.0,
+        BlackJackAction::SURRENDER => 0.5, // the half that's forfeited
+        _ => 1.0
+    }
+}
+
+/// Makes a move depending on the given player action, returning the number of bets at risk.
+/// Splits play more than one hand, so they go through handle_player_hands instead.
+/// `should_hit` is the EV-optimal hit/stand decision for the current hand, used by HIT_THEN_OPTIMAL
+/// and `target_total` is the total hands bust above (see RulesConfig)
+fn handle_player_action(
+    player_cards: &mut Vec<Card>,
+    action: &BlackJackAction,
+    draw_card: &mut impl FnMut() -> Option<Card>,
+    should_hit: &impl Fn(&[Card]) -> bool,
+    target_total: u8
+) -> f64 {
+    match action {
+        BlackJackAction::HIT(num_hits) => {
+            hit_until_bust(player_cards, *num_hits, draw_card, target_total);
+        }
+        BlackJackAction::HIT_THEN_OPTIMAL(num_hits) => {
+            hit_until_bust(player_cards, *num_hits, draw_card, target_total);
+            while should_hit(player_cards) && draw_into(player_cards, draw_card) {}
+        }
+        BlackJackAction::HIT_TO(target) => {
+            while best_hand_total_to(player_cards, target_total).is_some_and(|total| total < *target)
+                && draw_into(player_cards, draw_card) {}
+        }
+        BlackJackAction::STAND | BlackJackAction::SURRENDER => {
+            // do nothing if we stand, and surrendering ends the hand
+        }
+        BlackJackAction::DOUBLE | BlackJackAction::DOUBLE_FOR(_) => {
+            // doubling takes exactly one card and then stands
+            draw_into(player_cards, draw_card);
+        }
+        BlackJackAction::HIT_THEN_DOUBLE => {
+            draw_into(player_cards, draw_card);
+
+            // a busted hand is lost before there is any chance to double
+            if best_hand_total_to(player_cards, target_total).is_none() { return 1.0; }
+            draw_into(player_cards, draw_card);
+        }
+        BlackJackAction::SPLIT(_) => unreachable!("split hands are played by handle_player_hands")
+    }
+
+    action_stake(action)
+}
+
+/// Plays out the player's hands for an action, returning each final hand with the number
+/// of bets it has at risk. Splitting plays a hand from each of the paired cards (and from
+/// each card that pairs a split hand again, see RulesConfig::max_split_hands), one after
+/// another from the same shoe. Every other action plays the one hand.
+/// `should_double` decides whether a split hand doubles on its first two cards, and is only
+/// ever true when the rules allow doubling after a split
+fn handle_player_hands(
+    player_cards: &[Card],
+    action: &BlackJackAction,
+    draw_card: &mut impl FnMut() -> Option<Card>,
+    should_hit: &impl Fn(&[Card]) -> bool,
+    should_double: &impl Fn(&[Card]) -> bool,
+    rules: &RulesConfig
+) -> Vec<(Vec<Card>, f64)> {
+    let target_total = rules.target_total;
+    let BlackJackAction::SPLIT(num_hits) = action else {
+        let mut cards = player_cards.to_vec();
+        let stake = handle_player_action(&mut cards, action, draw_card, should_hit, target_total);
+        return vec![(cards, stake)];
+    };
+
+    let pair_value = player_cards[0].hard_value();
+    let mut first_cards = player_cards[..2].to_vec();
+    let mut hands = Vec::new();
+    while let Some(first_card) = first_cards.get(hands.len()) {
+        let mut cards = vec![first_card.clone()];
+
+        // a card that pairs the hand again starts another hand while the limit allows, which
+        // the cards in the shoe bound too
+        while draw_into(&mut cards, draw_card)
+            && first_cards.len() < rules.max_split_hands as usize
+            && cards[1].hard_value() == pair_value {
+            first_cards.extend(cards.pop());
+        }
+
+        // split aces only get one card, so only other pairs can double
+        let stake = if cards[0] == Card::Ace {
+            1.0
+        } else if should_double(&cards) {
+            draw_into(&mut cards, draw_card);
+            2.0
+        } else {
+            hit_until_bust(&mut cards, num_hits.saturating_sub(1), draw_card, target_total);
+            1.0
+        };
+        hands.push((cards, stake));
+    }
+    hands
+}
+
+/// Hits up to `num_hits` times, stopping early once the hand busts (no card can help it then)
+/// or the shoe runs out
+fn hit_until_bust(
+    player_cards: &mut Vec<Card>,
+    num_hits: u8,
+    draw_card: &mut impl FnMut() -> Option<Card>,
+    target_total: u8
+) {
+    for _ in 0..num_hits {
+        if best_hand_total_to(player_cards, target_total).is_none() || !draw_into(player_cards, draw_card) { break; }
+    }
+}
+
+/// Handles the dealer drawing until they reach the rules' stand total (17 in the usual
+/// rules) or higher, or the shoe runs out
+fn handle_dealer_action(
+    dealer_cards: &mut Vec<Card>,
+    draw_card: &mut impl FnMut() -> Option<Card>,
+    rules: &RulesConfig
+) {
+    while dealer_should_draw_under(dealer_cards, rules) && draw_into(dealer_cards, draw_card) {}
+}
+
+/// Like dealer_should_draw_to, but standing on the rules' stand total, and also drawing to a
+/// soft stand total (a soft 17 in the usual rules) when the rules have the dealer hit soft 17
+fn dealer_should_draw_under(dealer_cards: &[Card], rules: &RulesConfig) -> bool {
+    let (lowest_total, aces) = hard_total_and_aces(dealer_cards);
+    dealer_draws_on(lowest_total, aces, rules)
+}
+
+/// dealer_should_draw_under for a hand with the lowest total (aces as 1) and number of aces,
+/// which is all it depends on
+fn dealer_draws_on(lowest_total: u8, aces: u8, rules: &RulesConfig) -> bool {
+    let stand_total = rules.dealer_stand_total();
+    let target_total = rules.target_total;
+    let best_total = (lowest_total <= target_total)
+        .then(|| lowest_total + 10 * aces.min((target_total - lowest_total) / 10));
+    if best_total.is_some_and(|total| total < stand_total) { return true; }
+    if !rules.dealer_hits_soft_17 { return false; }
+
+    // soft if the total only stands by counting an ace as 11
+    best_total == Some(stand_total) && Some(lowest_total) != best_total
+}
+
+/// Draws a card into the hand, returning false if there was no card left to draw
+fn draw_into(cards: &mut Vec<Card>, draw_card: &mut impl FnMut() -> Option<Card>) -> bool {
+    match draw_card() {
+        Some(card) => {
+            cards.push(card);
+            true
+        }
+        None => false
+    }
+}
+
+/// The dealer keeps drawing until their best hand that doesn't bust is >= 17
+/// (a bust ace-as-11 iteration must not make them stand on e.g. a hard 16)
+fn dealer_should_draw(dealer_cards: &[Card]) -> bool {
+    dealer_should_draw_to(dealer_cards, 21)
+}
+
+/// Like dealer_should_draw, for a game that busts above `target_total` instead of 21
+fn dealer_should_draw_to(dealer_cards: &[Card], target_total: u8) -> bool {
+    best_hand_total_to(dealer_cards, target_total).is_some_and(|total| total < target_total - 4)
+}
+
+/// Evaluates one of the player's hands against the dealer's after they have both made their
+/// actions and then returns an outcome from the player's perspective.
+///
+/// They should win if their best hand beats the dealer's best total (None if they bust)
+/// Tie if their best hand matches the dealer's best total
+/// Lose if their best hand is worse than the dealer's best total
+/// A natural beats a 21 made with more cards and two naturals tie, a dealer's natural beats
+/// every other hand, and otherwise a five card Charlie wins. Whether the player has a natural
+/// depends on the hand they started from, see is_player_natural
+fn evaluate_hands(
+    players_cards: &[Card],
+    player_natural: bool,
+    dealer_total: Option<u8>,
+    dealer_natural: bool,
+    rules: &RulesConfig
+) -> GameOutcome {
+    match (player_natural, dealer_natural) {
+        (true, true) => GameOutcome::TIE,
+        (true, false) => GameOutcome::WIN,
+        (false, true) => GameOutcome::LOSS,
+        (false, false) if is_charlie(players_cards, rules) => GameOutcome::WIN,
+        (false, false) => compare_totals(best_hand_total_to(players_cards, rules.target_total), dealer_total)
+    }
+}
+
+/// Compares the player's and dealer's best totals (None if they bust), the player loses
+/// if they bust even if the dealer also busts
+fn compare_totals(player_best_option: Option<u8>, dealer_best_option: Option<u8>) -> GameOutcome {
+    match (player_best_option, dealer_best_option) {
+        (None, _) => GameOutcome::LOSS,
+        (Some(_), None) => GameOutcome::WIN,
+        (Some(player_best_value), Some(dealer_best_value)) => {
+            if player_best_value == dealer_best_value {
+                GameOutcome::TIE
+            } else if player_best_value > dealer_best_value {
+                GameOutcome::WIN
+            } else {
+                GameOutcome::LOSS
+            }
+        }
+    }
+}
+
+/// Whether the hand wins as a five card Charlie under the rules
+fn is_charlie(cards: &[Card], rules: &RulesConfig) -> bool {
+    rules.five_card_charlie && cards.len() >= 5 && best_hand_total_to(cards, rules.target_total).is_some()
+}
+
+/// A natural (or blackjack) is 21 on exactly two cards
+fn is_natural(cards: &[Card]) -> bool {
+    cards.len() == 2 && best_hand_total(cards) == Some(21)
+}
+
+/// Whether the player's final hand is a natural, which is keyed off the original two-card
+/// hand: drawing to 21 isn't a natural, and neither is a split ace that catches a ten
+fn is_player_natural(original_cards: &[Card], final_cards: &[Card], action: &BlackJackAction) -> bool {
+    !matches!(action, BlackJackAction::SPLIT(_))
+        && final_cards.len() == original_cards.len()
+        && is_natural(original_cards)
+}
+
+/// Returns the best total of a hand that doesn't bust, or None if every evaluation busts
+fn best_hand_total(cards: &[Card]) -> Option<u8> {
+    best_hand_total_to(cards, 21)
+}
+
+/// Like best_hand_total, for a game that busts above `target_total` instead of 21. Every way
+/// of counting the hand is its hard total plus 10 for each ace counted as 11, so the best
+/// counts as many aces as 11 as fit under the target
+fn best_hand_total_to(cards: &[Card], target_total: u8) -> Option<u8> {
+    let (hard_total, aces) = hard_total_and_aces(cards);
+    if hard_total > target_total { return None; }
+    Some(hard_total + 10 * aces.min((target_total - hard_total) / 10))
+}
+
+/// A hand's total counting every ace as 1 (its lowest evaluation) and how many aces it has
+fn hard_total_and_aces(cards: &[Card]) -> (u8, u8) {
+    cards.iter().fold((0, 0), |(total, aces), card| {
+        (total.saturating_add(card.hard_value()), aces + (*card == Card::Ace) as u8)
+    })
+}
+
+/// A hand is soft if its best total counts an Ace as 11 (i.e. it could drop by 10 without busting)
+fn is_soft_hand(cards: &[Card]) -> bool {
+    classify_hand(cards).1
+}
+
+/// Returns a hand's best total and whether it's soft. A bust hand is never soft, and its
+/// total is the lowest it can be counted as
+fn classify_hand(cards: &[Card]) -> (u8, bool) {
+    let (lowest_total, _) = hard_total_and_aces(cards);
+    match best_hand_total(cards) {
+        Some(best) => (best, best != lowest_total),
+        None => (lowest_total, false)
+    }
+}
+
+/// The chance the dealer busts playing out from their cards, simulated num_sims times from
+/// the shoe that's left and ignoring the player's hand entirely
+#[wasm_bindgen]
+pub fn dealer_bust_probability(data: UserDataStateHolder) -> Result<f64, JsValue> {
+    let data = parse_valid_state(data)?;
+    Ok(simulate_dealer_bust(&data))
+}
+
+fn simulate_dealer_bust(data: &UserDataState) -> f64 {
+    simulate_dealer_outcomes(data).bust
+}
+
+/// The dealer outcome chart: the chances of the dealer finishing on each total from 17 to 21,
+/// on a natural (counted apart from other 21s) or bust, playing out from their cards like
+/// dealer_bust_probability
+#[wasm_bindgen]
+pub fn dealer_outcome_chart(data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+    let data = parse_valid_state(data)?;
+    Ok(serde_wasm_bindgen::to_value(&simulate_dealer_outcomes(&data))?)
+}
+
+/// How often the dealer finished each way. Totals count down from the target total, so
+/// twenty_one is finishing on the target. The dealer only finishes under 17 when the rules
+/// have them stand lower (see RulesConfig::dealer_stand_value)
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct DealerOutcomeChart {
+    seventeen: f64,
+    eighteen: f64,
+    nineteen: f64,
+    twenty: f64,
+    twenty_one: f64,
+    blackjack: f64,
+    bust: f64,
+    under_seventeen: f64
+}
+
+fn simulate_dealer_outcomes(data: &UserDataState) -> DealerOutcomeChart {
+    let target_total = data.rules.target_total;
+    let deck = remaining_deck(data);
+    let mut rng = SimRng::from_seed(data.seed);
+    let mut shoe = Deck { cards: Vec::with_capacity(deck.cards.len()) };
+    // 17 to 21 (below the target), a natural, a bust, then anything under 17
+    let mut counts = [0u64; 8];
+    for _ in 0..data.num_sims {
+        shoe.refill_from(&deck);
+        let mut dealer_cards = data.dealer_card.clone();
+        handle_dealer_action(&mut dealer_cards, &mut || match data.rules.infinite_deck {
+            true => draw_from_infinite_deck(&mut rng),
+            false => shoe.take_random_card_using(&mut rng)
+        }, &data.rules);
+
+        let bucket = match best_hand_total_to(&dealer_cards, target_total) {
+            Some(21) if target_total == 21 && is_natural(&dealer_cards) => 5,
+            Some(total) => match target_total - total {
+                below @ 0..=4 => 4 - below as usize,
+                _ => 7
+            },
+            None => 6
+        };
+        counts[bucket] += 1;
+    }
+
+    let probability = |bucket: usize| ratio(counts[bucket], data.num_sims);
+    DealerOutcomeChart {
+        seventeen: probability(0),
+        eighteen: probability(1),
+        nineteen: probability(2),
+        twenty: probability(3),
+        twenty_one: probability(4),
+        blackjack: probability(5),
+        bust: probability(6),
+        under_seventeen: probability(7)
+    }
+}
+
+/// Converts a running count to a true count by dividing by the decks left in the shoe once
+/// the known cards are dealt, which is what `set_count_bias` expects
+#[wasm_bindgen]
+pub fn true_count(running_count: i32, data: UserDataStateHolder) -> Result<f64, JsValue> {
+    let data = parse_valid_state(data)?;
+    Ok(estimate_true_count(running_count, &data))
+}
+
+/// Counts the seen cards and the ones on the table with the Hi-Lo tags, returning the
+/// running count, the decks left and the true count they make
+#[wasm_bindgen]
+pub fn count_summary(data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+    let data = parse_valid_state(data)?;
+    Ok(serde_wasm_bindgen::to_value(&summarise_count(&data))?)
+}
+
+fn summarise_count(data: &UserDataState) -> CountSummary {
+    let running_count = data.current_cards.iter()
+        .chain(&data.dealer_card)
+        .chain(&data.seen_cards)
+        .map(|card| card.hi_lo_tag() as i32)
+        .sum();
+
+    CountSummary {
+        running_count,
+        decks_remaining: decks_remaining(data),
+        true_count: estimate_true_count(running_count, data)
+    }
+}
+
+/// The decks left in the shoe (custom or not) once the known cards are dealt
+fn decks_remaining(data: &UserDataState) -> f64 {
+    remaining_deck(data).cards.len() as f64 / 52.0
+}
+
+/// Divides by at least half a deck, so the last few cards (where the decks remaining
+/// approaches zero) don't make it explode
+fn estimate_true_count(running_count: i32, data: &UserDataState) -> f64 {
+    running_count as f64 / decks_remaining(data).max(0.5)
+}
+
+/// True counts swept when looking for a deviation index, in steps of a quarter
+const DEVIATION_INDEX_COUNTS: std::ops::RangeInclusive<i32> = -40..=40;
+
+/// The Hi-Lo true count at which the better of hitting and standing changes for a hand, e.g.
+/// about 0 for 16 vs 10, where you stand from the index up. Errors if the choice never changes
+/// between true counts of -10 and +10
+#[wasm_bindgen]
+pub fn deviation_index(player_hand: Vec<Card>, dealer_upcard: Card, num_decks: u8) -> Result<f64, JsValue> {
+    hand_deviation_index(player_hand, dealer_upcard, num_decks).map_err(|error| error.to_js())
+}
+
+/// deviation_index without the JS error, so the error can be checked natively
+fn hand_deviation_index(player_hand: Vec<Card>, dealer_upcard: Card, num_decks: u8) -> Result<f64, SimulationError> {
+    let data = UserDataState {
+        current_cards: player_hand.into_iter().filter(|card| *card != Card::Empty).collect(),
+        dealer_card: vec![dealer_upcard],
+        num_decks,
+        // nothing is simulated, these only have to be valid
+        bet_size: 1.0,
+        num_sims: 1,
+        ..Default::default()
+    };
+    data.validate()?;
+
+    find_deviation_index(&data).ok_or_else(|| {
+        SimulationError::new(SimulationErrorKind::NotApplicable, "the better of hitting and standing never changes between true counts of -10 and +10")
+    })
+}
+
+/// Sweeps the count bias and returns the first true count where the exact hit-over-stand
+/// advantage changes sign, interpolated between the two counts either side of it
+fn find_deviation_index(data: &UserDataState) -> Option<f64> {
+    let hit_advantage = |true_count: f64| {
+        let data = UserDataState { count_bias: true_count, ..data.clone() };
+        HitStandSolver::new(&remaining_deck(&data), &data.dealer_card, true, &data.rules).hit_advantage(&data.current_cards)
+    };
+
+    let mut previous: Option<(f64, f64)> = None;
+    for true_count in DEVIATION_INDEX_COUNTS.map(|quarters| quarters as f64 / 4.0) {
+        let advantage = hit_advantage(true_count)?;
+        match previous {
+            Some((previous_count, previous_advantage)) if (previous_advantage > 0.0) != (advantage > 0.0) => {
+                let fraction = previous_advantage / (previous_advantage - advantage);
+                return Some(previous_count + fraction * (true_count - previous_count));
+            }
+            _ => previous = Some((true_count, advantage))
+        }
+    }
+    None
+}
+
+/// The standard normal quantile for a probability above 0.5, using the rational approximation
+/// from Abramowitz and Stegun (26.2.23), which is within 4.5e-4
+fn normal_quantile(probability: f64) -> f64 {
+    let t = (-2.0 * (1.0 - probability).ln()).sqrt();
+    t - (2.515517 + 0.802853 * t + 0.010328 * t * t) / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t)
+}
+
+/// The win probability at which a bet paying `payout` to 1 breaks even, ignoring ties
+/// (e.g. 0.5 for an even-money bet or 0.4 for a 3:2 payout)
+#[wasm_bindgen]
+pub fn break_even_win_probability(payout: f64) -> f64 {
+    finite_or_zero(1.0 / (1.0 + payout))
+}
+
+/// The hand's total as it's usually shown: the highest way of counting it that's 21 or less,
+/// or the lowest if every way busts (e.g. 25 for King, Queen, Five)
+#[wasm_bindgen]
+pub fn best_hand_value(cards: Vec<Card>) -> u8 {
+    let values = evaluate_hand(&cards);
+    let best = values.iter().filter(|&&value| value <= 21).max();
+    best.or(values.iter().min()).copied().unwrap_or(0)
+}
+
+/// Labels a hand for display, e.g. "Blackjack", "21", "Soft 17", "Hard 12" or "Bust"
+#[wasm_bindgen]
+pub fn hand_label(cards: Vec<Card>) -> String {
+    let cards: Vec<Card> = cards
+        .into_iter()
+        .filter(|card| *card != Card::Empty)
+        .collect();
+
+    match classify_hand(&cards) {
+        (total, _) if total > 21 => "Bust".to_string(),
+        (21, _) if cards.len() == 2 => "Blackjack".to_string(),
+        (21, _) => "21".to_string(), // a 21 made with 3+ cards is not a natural
+        (total, true) => format!("Soft {}", total),
+        (total, false) => format!("Hard {}", total),
+    }
+}
+
+/// Textbook basic strategy for a multi-deck shoe, one character per dealer upcard 2-10 then Ace:
+/// H = hit, S = stand, D = double (hit if not allowed), d = double (stand if not allowed), P = split
+const BASIC_STRATEGY_HARD: [&str; 17] = [
+    "HHHHHHHHHH", // 5
+    "HHHHHHHHHH", // 6
+    "HHHHHHHHHH", // 7
+    "HHHHHHHHHH", // 8
+    "HDDDDHHHHH", // 9
+    "DDDDDDDDHH", // 10
+    "DDDDDDDDDD", // 11
+    "HHSSSHHHHH", // 12
+    "SSSSSHHHHH", // 13
+    "SSSSSHHHHH", // 14
+    "SSSSSHHHHH", // 15
+    "SSSSSHHHHH", // 16
+    "SSSSSSSSSS", // 17
+    "SSSSSSSSSS", // 18
+    "SSSSSSSSSS", // 19
+    "SSSSSSSSSS", // 20
+    "SSSSSSSSSS", // 21
+];
+
+/// Soft totals 12 to 21, same layout as BASIC_STRATEGY_HARD
+const BASIC_STRATEGY_SOFT: [&str; 10] = [
+    "HHHHHHHHHH", // 12
+    "HHHDDHHHHH", // 13
+    "HHHDDHHHHH", // 14
+    "HHDDDHHHHH", // 15
+    "HHDDDHHHHH", // 16
+    "HDDDDHHHHH", // 17
+    "SddddSSHHH", // 18
+    "SSSSSSSSSS", // 19
+    "SSSSSSSSSS", // 20
+    "SSSSSSSSSS", // 21
+];
+
+/// Pairs of twos through tens then aces, same layout as BASIC_STRATEGY_HARD
+const BASIC_STRATEGY_PAIRS: [&str; 10] = [
+    "PPPPPPHHHH", // 2s
+    "PPPPPPHHHH", // 3s
+    "HHHPPHHHHH", // 4s
+    "DDDDDDDDHH", // 5s
+    "PPPPPHHHHH", // 6s
+    "PPPPPPHHHH", // 7s
+    "PPPPPPPPPP", // 8s
+    "PPPPPSPPSS", // 9s
+    "SSSSSSSSSS", // 10s
+    "PPPPPPPPPP", // aces
+];
+
+/// Looks up the textbook basic strategy action ("hit", "stand", "double" or "split") so
+/// simulated recommendations can be compared against it. The dealer upcard is 2-10, with
+/// an ace given as 1 or 11, and pairs are identified by their total (aces are a soft 12)
+#[wasm_bindgen]
+pub fn textbook_action(player_total: u8, is_soft: bool, is_pair: bool, dealer_upcard: u8) -> String {
+    let column = match dealer_upcard {
+        2..=10 => (dealer_upcard - 2) as usize,
+        1 | 11 => 9,
+        _ => return "unknown".to_string()
+    };
+
+    // a bust hand has no action
+    match StrategyTable::textbook().code(player_total, is_soft, is_pair, column) {
+        Some(b'H') => "hit",
+        Some(b'S') => "stand",
+        Some(b'D' | b'd') => "double",
+        Some(_) => "split",
+        None => "unknown",
+    }.to_string()
+}
+
+/// The textbook_action for the player's hand against the dealer's upcard
+fn basic_strategy_action(data: &UserDataState) -> String {
+    let (player_total, is_soft) = classify_hand(&data.current_cards);
+    // an ace's lowest value is 1, which textbook_action reads as an ace
+    let dealer_upcard = data.dealer_card[0].hard_value();
+    textbook_action(player_total, is_soft, can_split_hand(&data.current_cards), dealer_upcard)
+}
+
+/// A complete playing strategy laid out like the basic strategy tables: 17 hard rows (5-21),
+/// 10 soft rows (12-21) and 10 pair rows (twos to tens then aces), each with one code per
+/// dealer upcard. Pair rows may also use P to split
+#[wasm_bindgen]
+pub struct StrategyTable {
+    hard: Vec<String>,
+    soft: Vec<String>,
+    pairs: Vec<String>
+}
+
+#[wasm_bindgen]
+impl StrategyTable {
+    /// Builds a strategy from its rows, erroring if a table has the wrong number of rows or
+    /// a row isn't 10 valid codes
+    pub fn new(hard: Vec<String>, soft: Vec<String>, pairs: Vec<String>) -> Result<StrategyTable, JsValue> {
+        StrategyTable::from_rows(hard, soft, pairs).map_err(|error| error.to_js())
+    }
+
+    /// The textbook basic strategy
+    pub fn textbook() -> StrategyTable {
+        let rows = |table: &[&str]| table.iter().map(|row| row.to_string()).collect();
+        StrategyTable {
+            hard: rows(&BASIC_STRATEGY_HARD),
+            soft: rows(&BASIC_STRATEGY_SOFT),
+            pairs: rows(&BASIC_STRATEGY_PAIRS)
+        }
+    }
+}
+
+/// What a player following a strategy table does next with a hand
+#[derive(Debug, PartialEq)]
+enum StrategyMove {
+    Hit,
+    Stand,
+    Double,
+    Split
+}
+
+impl StrategyTable {
+    fn from_rows(hard: Vec<String>, soft: Vec<String>, pairs: Vec<String>) -> Result<StrategyTable, SimulationError> {
+        let check = |name: &str, rows: &[String], num_rows: usize, codes: &[u8]| {
+            let message = if rows.len() != num_rows {
+                format!("the {} table needs {} rows but has {}", name, num_rows, rows.len())
+            } else if let Some(row) = rows.iter().find(|row| row.len() != 10 || !row.bytes().all(|code| codes.contains(&code))) {
+                format!("{:?} in the {} table isn't 10 of {}", row, name, String::from_utf8_lossy(codes))
+            } else {
+                return Ok(());
+            };
+            Err(SimulationError::new(SimulationErrorKind::InvalidStrategy, &message))
+        };
+        check("hard", &hard, BASIC_STRATEGY_HARD.len(), b"HSDd")?;
+        check("soft", &soft, BASIC_STRATEGY_SOFT.len(), b"HSDd")?;
+        check("pair", &pairs, BASIC_STRATEGY_PAIRS.len(), b"HSDdP")?;
+
+        Ok(StrategyTable { hard, soft, pairs })
+    }
+
+    /// The code for a hand in the given upcard column, keyed like `textbook_action`. None if
+    /// the total busts
+    fn code(&self, player_total: u8, is_soft: bool, is_pair: bool, column: usize) -> Option<u8> {
+        let pair_row = match (is_pair, is_soft, player_total) {
+            (true, true, 12) => Some(9), // aces
+            (true, false, 4..=20) if player_total.is_multiple_of(2) => Some((player_total / 2 - 2) as usize),
+            _ => None
+        };
+        let row = match (pair_row, is_soft, player_total) {
+            (Some(row), _, _) => &self.pairs[row],
+            (None, true, 12..=21) => &self.soft[(player_total - 12) as usize],
+            (None, _, 0..=4) => &self.hard[0],
+            (None, _, 5..=21) => &self.hard[(player_total - 5) as usize],
+            (None, _, _) => return None
+        };
+
+        Some(row.as_bytes()[column])
+    }
+
+    /// The next move for a hand against the dealer's upcard. Doubles fall back to the
+    /// table's choice (D hits, d stands) when they aren't allowed, and a bust hand stands
+    fn next_move(&self, cards: &[Card], upcard: &Card, can_double: bool, can_split: bool) -> StrategyMove {
+        let column = match upcard.hard_value() {
+            value @ 2.. => (value - 2) as usize,
+            _ => 9 // an ace
+        };
+        let Some(total) = best_hand_total(cards) else { return StrategyMove::Stand };
+        let is_pair = can_split && can_split_hand(cards);
+
+        match self.code(total, is_soft_hand(cards), is_pair, column) {
+            Some(b'H') => StrategyMove::Hit,
+            Some(b'D' | b'd') if can_double => StrategyMove::Double,
+            Some(b'D') => StrategyMove::Hit,
+            Some(b'P') => StrategyMove::Split,
+            _ => StrategyMove::Stand
+        }
+    }
+}
+
+/// Plays `num_rounds` full rounds following the strategy under the rules and returns its EV in
+/// bets per round, so a negative value is the house edge against that chart
+#[wasm_bindgen]
+pub fn strategy_ev(
+    strategy: &StrategyTable,
+    rules: &RulesConfig,
+    num_decks: u8,
+    num_rounds: u32
+) -> Result<f64, JsValue> {
+    // whole rounds are only played to the normal target
+    let rules = RulesConfig { target_total: 21, ..*rules };
+    check_rounds(&rules, num_decks, num_rounds).map_err(|error| SimulationError::from(error).to_js())?;
+
+    Ok(finite_or_zero(simulate_strategy_rounds(strategy, &rules, num_decks, num_rounds)))
+}
+
+/// Checks the rules and shoe whole rounds are played with, like UserDataState::validate does
+/// for a single hand
+fn check_rounds(rules: &RulesConfig, num_decks: u8, num_rounds: u32) -> Result<(), InvalidStateError> {
+    rules.validate()?;
+    if num_decks == 0 { return Err(InvalidStateError::NoDecks); }
+    if num_decks > MAX_DECKS { return Err(InvalidStateError::TooManyDecks(num_decks)); }
+    if num_rounds == 0 { return Err(InvalidStateError::NoSimulations); }
+    Ok(())
+}
+
+/// The house edge under the rules as a percentage of each bet: how much a player following
+/// the textbook basic strategy loses on average over `num_rounds` rounds dealt from a shoe of
+/// `num_decks` decks. Errors like strategy_ev if the rules or counts aren't possible
+#[wasm_bindgen]
+pub fn house_edge(rules: &RulesConfig, num_decks: u8, num_rounds: u32) -> Result<f64, JsValue> {
+    strategy_ev(&StrategyTable::textbook(), rules, num_decks, num_rounds).map(|ev| -100.0 * ev)
+}
+
+/// Deals rounds from a shoe that's reshuffled once the rules' penetration has been dealt and
+/// averages the net result in bets. Naturals pay the blackjack payout and the dealer peeks, so
+/// a dealer natural only takes the original bet, unless there's no hole card (ENHC) and it
+/// takes everything bet once the player has acted. Pairs split once into two hands that may
+/// double if the rules allow it, except split aces which take one card each
+fn simulate_strategy_rounds(strategy: &StrategyTable, rules: &RulesConfig, num_decks: u8, num_rounds: u32) -> f64 {
+    let full_shoe = Deck::new(&num_decks);
+    let reshuffle_at = (full_shoe.cards.len() as f64 * (1.0 - rules.penetration)) as usize;
+    let mut shoe = full_shoe.clone();
+    let mut net_bets = 0.0;
+
+    for _ in 0..num_rounds {
+        if shoe.cards.len() < reshuffle_at { shoe = full_shoe.clone(); }
+        let draw_card = &mut || {
+            // a round that outlasts the cut card takes a fresh shoe rather than running dry
+            if shoe.cards.is_empty() { shoe = full_shoe.clone(); }
+            shoe.take_random_card_from_deck()
+        };
+
+        let mut player_cards = Vec::with_capacity(2);
+        let mut dealer_cards = Vec::with_capacity(2);
+        for _ in 0..2 {
+            draw_into(&mut player_cards, draw_card);
+            draw_into(&mut dealer_cards, draw_card);
+        }
+        let upcard = dealer_cards[0].clone();
+
+        net_bets += match (is_natural(&player_cards), is_natural(&dealer_cards)) {
+            (true, true) => 0.0,
+            (true, false) => rules.blackjack_payout,
+            (false, true) if !rules.european_no_hole_card => -1.0,
+            // without a hole card the dealer's natural only shows once the player has played
+            (false, true) => -play_strategy_hands(strategy, player_cards, &upcard, rules.double_after_split, draw_card)
+                .iter()
+                .map(|(_, stake)| stake)
+                .sum::<f64>(),
+            (false, false) => {
+                let hands = play_strategy_hands(strategy, player_cards, &upcard, rules.double_after_split, draw_card);
+                handle_dealer_action(&mut dealer_cards, draw_card, rules);
+                let dealer_total = best_hand_total(&dealer_cards);
+
+                hands
+                    .iter()
+                    .map(|(cards, stake)| match compare_totals(best_hand_total(cards), dealer_total) {
+                        _ if is_charlie(cards, rules) => *stake,
+                        GameOutcome::WIN => *stake,
+                        GameOutcome::LOSS => -*stake,
+                        GameOutcome::TIE => 0.0
+                    })
+                    .sum()
+            }
+        };
+    }
+
+    net_bets / num_rounds as f64
+}
+
+/// Plays out the player's starting hand, returning each final hand with its stake in bets.
+/// Split hands can only double with `double_after_split`
+fn play_strategy_hands(
+    strategy: &StrategyTable,
+    mut cards: Vec<Card>,
+    upcard: &Card,
+    double_after_split: bool,
+    draw_card: &mut impl FnMut() -> Option<Card>
+) -> Vec<(Vec<Card>, f64)> {
+    if strategy.next_move(&cards, upcard, true, true) != StrategyMove::Split {
+        let stake = play_strategy_hand(strategy, &mut cards, upcard, true, draw_card);
+        return vec![(cards, stake)];
+    }
+
+    let split_aces = cards[0] == Card::Ace;
+    cards
+        .into_iter()
+        .map(|card| {
+            let mut hand = vec![card];
+            draw_into(&mut hand, draw_card);
+            let stake = match split_aces {
+                true => 1.0,
+                false => play_strategy_hand(strategy, &mut hand, upcard, double_after_split, draw_card)
+            };
+            (hand, stake)
+        })
+        .collect()
+}
+
+/// Hits, stands or doubles a single hand as the strategy says, returning its stake in bets.
+/// Only a two-card hand can double, and only if `can_double`
+fn play_strategy_hand(
+    strategy: &StrategyTable,
+    hand: &mut Vec<Card>,
+    upcard: &Card,
+    can_double: bool,
+    draw_card: &mut impl FnMut() -> Option<Card>
+) -> f64 {
+    loop {
+        match strategy.next_move(hand, upcard, can_double && hand.len() == 2, false) {
+            StrategyMove::Hit => if !draw_into(hand, draw_card) { return 1.0; },
+            StrategyMove::Double => {
+                draw_into(hand, draw_card);
+                return 2.0;
+            }
+            StrategyMove::Stand | StrategyMove::Split => return 1.0
+        }
+    }
+}
+
+/// Check if the player's hand can be split, if it can, return true. Cards pair by value,
+/// so any two ten-value cards can be split
+fn can_split_hand(hand: &[Card]) -> bool {
+    hand.len() == 2 && hand[0].hard_value() == hand[1].hard_value()
+}
+
+
+/// Evaluates a hand and returns a list of possible values. The simulation works from hard
+/// totals instead (see best_hand_total_to), which needs no allocations
+fn evaluate_hand(cards: &[Card]) -> Vec<u8> {
+    // an Empty card has no values, which would drop every combination, so it is skipped
+    let value_mapping: Vec<Vec<u8>> = cards
+        .iter()
+        .filter(|card| **card != Card::Empty)
+        .map(|card| card.get_card_values())
+        .collect();
+    generate_value_combinations(&value_mapping)
+}
+
+/// Generates all combinations of evaluations of a hand
+fn generate_value_combinations(card_values: &[Vec<u8>]) -> Vec<u8> {
+    let n =  card_values.len();
+    let mut results = vec![];
+    let mut stack = vec![(0, 0u8)];
+
+    while let Some((index, current_sum)) = stack.pop() {
+        if index == n { // assure that we stop once we have combinations of length n
+            results.push(current_sum);
+            continue;
+        }
+
+        for &value in &card_values[index] {
+            // saturate rather than overflow on huge hands, any sum this big is a bust anyway
+            stack.push((index + 1, current_sum.saturating_add(value)));
+        }
+    }
+
+    results
+}
+
+/// How many of a card are left in the shoe
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CardCount {
+    card: String,
+    count: u32
+}
+
+/// Counts each card left in the shoe once the known cards are taken out (and the shoe is
+/// reshaped to any count bias), from Ace to King. The ten-value cards are counted separately
+#[wasm_bindgen]
+pub fn remaining_deck_composition(data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+    let data = parse_valid_state(data)?;
+    Ok(serde_wasm_bindgen::to_value(&deck_composition(&data))?)
+}
+
+fn deck_composition(data: &UserDataState) -> Vec<CardCount> {
+    let deck = remaining_deck(data);
+    DECK_CARDS
+        .iter()
+        .map(|card| CardCount {
+            card: format!("{:?}", card),
+            count: deck.cards.iter().filter(|other| *other == card).count() as u32
+        })
+        .collect()
+}
+
+/// The change in the player's EV (in dollars) from removing one more card of a rank from the shoe
+#[derive(Serialize, Deserialize)]
+struct CardRemovalEffect {
+    card: String,
+    ev_change: f64
+}
+
+/// Computes the "effect of removal" table: how the player's EV shifts when one extra card of
+/// each rank is removed from the shoe. EVs are computed exactly (see exact_best_ev) since the
+/// effects are far smaller than Monte Carlo noise
+#[wasm_bindgen]
+pub fn card_removal_effects(data: UserDataStateHolder) -> Result<JsValue, JsValue> {
+    let data = parse_valid_state(data)?;
+    Ok(serde_wasm_bindgen::to_value(&generate_card_removal_effects(&data))?)
+}
+
+fn generate_card_removal_effects(data: &UserDataState) -> Vec<CardRemovalEffect> {
+    let mut counts = remaining_deck(data).rank_counts();
+    let base_ev = exact_best_ev(&data.current_cards, &data.dealer_card, &mut counts, &data.rules);
+
+    RANKS
+        .iter()
+        .enumerate()
+        .map(|(index, card)| {
+            let ev_change = if counts[index] == 0 {
+                0.0 // nothing left to remove
+            } else {
+                counts[index] -= 1;
+                let removed_ev = exact_best_ev(&data.current_cards, &data.dealer_card, &mut counts, &data.rules);
+                counts[index] += 1;
+                (removed_ev - base_ev) * data.bet_size
+            };
+
+            CardRemovalEffect {
+                card: format!("{:?}", card),
+                ev_change
+            }
+        })
+        .collect()
+}
+
+/// exact_natural_outcome for natural_outcome, erroring if the player's hand isn't a natural
+fn natural_outcome_of(data: &UserDataState) -> Result<ProbabilityValueOutcomes, SimulationError> {
+    exact_natural_outcome(data)
+        .ok_or_else(|| SimulationError::new(SimulationErrorKind::NotApplicable, "the player's hand isn't a natural"))
+}
+
+/// Exact outcome of a player natural, or None if the player's hand isn't a natural
+fn exact_natural_outcome(data: &UserDataState) -> Option<ProbabilityValueOutcomes> {
+    if !is_natural(&data.current_cards) { return None; }
+
+    let counts = remaining_deck(data).rank_counts();
+    let tie = dealer_natural_probability(&data.dealer_card, &counts);
+    let win = 1.0 - tie;
+    let mut outcomes = ProbabilityValueOutcomes {
+        estimated_value: finite_or_zero(win * data.rules.blackjack_payout * data.bet_size),
+        ev_per_bet: win * data.rules.blackjack_payout,
+        blackjack_payout: data.rules.blackjack_payout,
+        win,
+        loss: 0.0,
+        tie,
+        blackjack: win,
+        average_cards_drawn: 0.0,
+        ..ProbabilityValueOutcomes::new()
+    };
+    outcomes.net_variance = outcomes.single_hand_variance();
+    Some(outcomes)
+}
+
+/// Exact outcome of standing or hitting once, which draw at most one card for the player, by
+/// enumerating every card that can come out of the shoe weighted by how many are left: the
+/// dealer's hole card (a natural ends the round), then the player's card and the dealer's
+/// draws. None for deeper draws and for the rules the enumeration doesn't cover, which are
+/// simulated instead
+fn exact_shallow_outcomes(data: &UserDataState, action: &BlackJackAction) -> Option<ProbabilityValueOutcomes> {
+    let player_draws = match action {
+        BlackJackAction::STAND => 0,
+        BlackJackAction::HIT(1) => 1,
+        _ => return None
+    };
+    let rules = &data.rules;
+    let covered = rules.target_total == 21
+        && !rules.infinite_deck
+        && !rules.european_no_hole_card
+        && !rules.five_card_charlie
+        && data.fixed_dealer_total.is_none()
+        && data.on_deck_empty == DeckEmptyPolicy::TreatAsStand
+        && data.num_other_players == 0
+        && !is_natural(&data.current_cards);
+    if !covered { return None; }
+
+    let mut counts = remaining_deck(data).rank_counts();
+    let mut player_cards = data.current_cards.clone();
+    let mut enumeration = ShallowEnumeration::new(&data.rules);
+    let remaining: u32 = counts.iter().sum();
+    match data.dealer_card.as_slice() {
+        [upcard] if remaining > 0 => {
+            for (index, card) in RANKS.iter().enumerate() {
+                if counts[index] == 0 { continue; }
+
+                let probability = counts[index] as f64 / remaining as f64;
+                let dealer_cards = [upcard.clone(), card.clone()];
+                counts[index] -= 1;
+                if is_natural(&dealer_cards) {
+                    enumeration.lose_to_natural(&player_cards, probability);
+                } else {
+                    enumeration.play(&mut player_cards, player_draws, &dealer_cards, &mut counts, probability);
+                }
+                counts[index] += 1;
+            }
+        }
+        // a known natural was found on the peek, before the player draws
+        dealer_cards if is_natural(dealer_cards) => enumeration.lose_to_natural(&player_cards, 1.0),
+        dealer_cards => enumeration.play(&mut player_cards, player_draws, dealer_cards, &mut counts, 1.0)
+    }
+    Some(enumeration.to_outcomes(action, data))
+}
+
+/// Probability-weighted results accumulated by exact_shallow_outcomes, with the same meaning
+/// as the simulated ones (totals of 0 are busts)
+struct ShallowEnumeration {
+    win: f64,
+    loss: f64,
+    tie: f64,
+    bust: f64,
+    net_bets: f64,
+    player_draws: f64,
+    player_totals: [f64; 22],
+    dealer_totals: [f64; 22],
+    dealer_memo: DealerTotalsMemo
+}
+
+impl ShallowEnumeration {
+    fn new(rules: &RulesConfig) -> Self {
+        ShallowEnumeration {
+            win: 0.0,
+            loss: 0.0,
+            tie: 0.0,
+            bust: 0.0,
+            net_bets: 0.0,
+            player_draws: 0.0,
+            player_totals: [0.0; 22],
+            dealer_totals: [0.0; 22],
+            dealer_memo: DealerTotalsMemo::new(rules)
+        }
+    }
+
+    /// A dealer natural found on the peek, which takes the original bet
+    fn lose_to_natural(&mut self, player_cards: &[Card], probability: f64) {
+        self.loss += probability;
+        self.net_bets -= probability;
+        self.player_totals[best_hand_total(player_cards).unwrap_or(0) as usize] += probability;
+        self.dealer_totals[21] += probability;
+    }
+
+    /// Deals the player's remaining draws, then settles against every way the dealer can finish
+    fn play(
+        &mut self,
+        player_cards: &mut Vec<Card>,
+        draws: u8,
+        dealer_cards: &[Card],
+        counts: &mut [u32; 10],
+        probability: f64
+    ) {
+        let remaining: u32 = counts.iter().sum();
+        if draws == 0 || remaining == 0 {
+            self.settle(player_cards, dealer_cards, counts, probability);
+            return;
+        }
+
+        for (index, card) in RANKS.iter().enumerate() {
+            if counts[index] == 0 { continue; }
+
+            let draw_probability = probability * counts[index] as f64 / remaining as f64;
+            self.player_draws += draw_probability;
+            counts[index] -= 1;
+            player_cards.push(card.clone());
+            self.play(player_cards, draws - 1, dealer_cards, counts, draw_probability);
+            player_cards.pop();
+            counts[index] += 1;
+        }
+    }
+
+    fn settle(&mut self, player_cards: &[Card], dealer_cards: &[Card], counts: &mut [u32; 10], probability: f64) {
+        let player_total = best_hand_total(player_cards);
+        self.player_totals[player_total.unwrap_or(0) as usize] += probability;
+        if player_total.is_none() { self.bust += probability; }
+
+        let mut dealer_totals = [0.0; 22];
+        self.dealer_memo.add_totals(dealer_cards, counts, probability, &mut dealer_totals);
+        for (dealer_total, weight) in dealer_totals.into_iter().enumerate() {
+            self.dealer_totals[dealer_total] += weight;
+            match compare_totals(player_total, (dealer_total > 0).then_some(dealer_total as u8)) {
+                GameOutcome::WIN => { self.win += weight; self.net_bets += weight; }
+                GameOutcome::LOSS => { self.loss += weight; self.net_bets -= weight; }
+                GameOutcome::TIE => self.tie += weight
+            }
+        }
+    }
+
+    fn to_outcomes(&self, action: &BlackJackAction, data: &UserDataState) -> ProbabilityValueOutcomes {
+        let (modal_player_total, modal_player_total_frequency) = modal_total(&self.player_totals, 1.0);
+        let (modal_dealer_total, modal_dealer_total_frequency) = modal_total(&self.dealer_totals, 1.0);
+        let mut outcomes = ProbabilityValueOutcomes {
+            win: self.win,
+            loss: self.loss,
+            tie: self.tie,
+            blackjack: 0.0,
+            bust: self.bust,
+            average_cards_drawn: self.player_draws,
+            stake: action_stake(action),
+            ev_per_bet: self.net_bets,
+            blackjack_payout: data.rules.blackjack_payout,
+            std_error: Some(0.0),
+            modal_player_total,
+            modal_player_total_frequency,
+            modal_dealer_total,
+            modal_dealer_total_frequency,
+            exact: true,
+            ..ProbabilityValueOutcomes::new()
+        };
+        outcomes.estimated_value = outcomes.ev_at_bet(data.bet_size);
+        outcomes.net_variance = outcomes.single_hand_variance();
+        outcomes
+    }
+}
+
+/// The chance the dealer's hole card completes a natural: an ace needs a ten-value card and
+/// a ten-value card needs an ace, while any other upcard can't make one. Dealer cards past
+/// the upcard already show whether it's a natural
+fn dealer_natural_probability(dealer_cards: &[Card], counts: &[u32; 10]) -> f64 {
+    if is_natural(dealer_cards) { return 1.0; }
+
+    let remaining: u32 = counts.iter().sum();
+    let needed = match dealer_cards {
+        [card] => match card.rank_index() {
+            Some(0) => 9,
+            Some(9) => 0,
+            _ => return 0.0
+        },
+        _ => return 0.0
+    };
+    ratio(counts[needed] as u64, remaining)
+}
+
+/// Exact EV (in bets) of the better of standing and hitting once, given the remaining rank counts
+fn exact_best_ev(player_cards: &[Card], dealer_cards: &[Card], counts: &mut [u32; 10], rules: &RulesConfig) -> f64 {
+    let stand_ev = exact_stand_ev(player_cards, dealer_cards, counts, rules);
+    let hit_once_ev = exact_hit_once_ev(player_cards, dealer_cards, counts, rules);
+    stand_ev.max(hit_once_ev)
+}
+
+/// Exact EV (in bets) of hitting exactly once and then standing
+fn exact_hit_once_ev(player_cards: &[Card], dealer_cards: &[Card], counts: &mut [u32; 10], rules: &RulesConfig) -> f64 {
+    let remaining: u32 = counts.iter().sum();
+    if remaining == 0 {
+        return exact_stand_ev(player_cards, dealer_cards, counts, rules);
+    }
+
+    let mut player_cards = player_cards.to_vec();
+    let mut ev = 0.0;
+    for (index, card) in RANKS.iter().enumerate() {
+        if counts[index] == 0 { continue; }
+
+        let probability = counts[index] as f64 / remaining as f64;
+        counts[index] -= 1;
+        player_cards.push(card.clone());
+        ev += probability * exact_stand_ev(&player_cards, dealer_cards, counts, rules);
+        player_cards.pop();
+        counts[index] += 1;
+    }
+    ev
+}
+
+/// Exact EV (in bets) of standing, from the distribution of the dealer's final totals
+fn exact_stand_ev(player_cards: &[Card], dealer_cards: &[Card], counts: &mut [u32; 10], rules: &RulesConfig) -> f64 {
+    let player_total = match best_hand_total(player_cards) {
+        Some(total) => total,
+        None => return -1.0 // a bust loses regardless of the dealer
+    };
+
+    let mut dealer_totals = [0.0; 22];
+    exact_dealer_totals(dealer_cards, counts, 1.0, &mut dealer_totals, rules);
+
+    stand_ev_against(player_total, &dealer_totals)
+}
+
+/// EV (in bets) of standing on a total against a distribution of dealer final totals (0 is a bust)
+fn stand_ev_against(player_total: u8, dealer_totals: &[f64; 22]) -> f64 {
+    dealer_totals
+        .iter()
+        .enumerate()
+        .map(|(dealer_total, probability)| {
+            let dealer_total = dealer_total as u8;
+            if dealer_total == 0 || dealer_total < player_total {
+                *probability // dealer busts (total 0) or player has the better hand
+            } else if dealer_total > player_total {
+                -*probability
+            } else {
+                0.0
+            }
+        })
+        .sum()
+}
+
+/// Plays out every possible dealer draw under the rules, adding the probability of each final
+/// best total to dealer_totals (a bust is recorded as 0)
+fn exact_dealer_totals(
+    dealer_cards: &[Card],
+    counts: &mut [u32; 10],
+    probability: f64,
+    dealer_totals: &mut [f64; 22],
+    rules: &RulesConfig
+) {
+    DealerTotalsMemo::new(rules).add_totals(dealer_cards, counts, probability, dealer_totals);
+}
+
+/// Distributions of the dealer's final totals already worked out, so each is only played out
+/// once. Different orders of the same draws leave the same cards in the shoe and the same
+/// hand, which is all the rest of the dealer's play depends on, so a distribution is keyed by
+/// the rank counts left and the hand as its lowest total (aces as 1) and whether it has an ace.
+/// The dealer draws by the rules (see dealer_draws_on), whose target total must be 21: that's
+/// all 22 totals hold, and it's what lets one ace stand in for any number of them.
+/// Only the exact calculations use it. The simulation still plays out the dealer every time,
+/// since the distribution depends on the cards the player drew in that simulation
+struct DealerTotalsMemo {
+    rules: RulesConfig,
+    distributions: HashMap<([u32; 10], u8, bool), [f64; 22]>
+}
+
+impl DealerTotalsMemo {
+    fn new(rules: &RulesConfig) -> Self {
+        DealerTotalsMemo { rules: *rules, distributions: HashMap::new() }
+    }
+
+    /// Adds `probability` times the distribution of the dealer's final totals from their cards
+    fn add_totals(&mut self, dealer_cards: &[Card], counts: &mut [u32; 10], probability: f64, dealer_totals: &mut [f64; 22]) {
+        let (lowest_total, aces) = hard_total_and_aces(dealer_cards);
+        let distribution = self.distribution(lowest_total, aces > 0, counts);
+        for (total, total_probability) in dealer_totals.iter_mut().zip(distribution) {
+            *total += probability * total_probability;
+        }
+    }
+
+    fn distribution(&mut self, lowest_total: u8, has_ace: bool, counts: &mut [u32; 10]) -> [f64; 22] {
+        let best_total = if has_ace && lowest_total + 10 <= 21 { lowest_total + 10 } else { lowest_total };
+        let mut distribution = [0.0; 22];
+        let remaining: u32 = counts.iter().sum();
+        if !dealer_draws_on(lowest_total, has_ace as u8, &self.rules) || remaining == 0 {
+            distribution[if best_total > 21 { 0 } else { best_total as usize }] = 1.0;
+            return distribution;
+        }
+
+        let key = (*counts, lowest_total, has_ace);
+        if let Some(distribution) = self.distributions.get(&key) { return *distribution; }
+
+        for index in 0..RANKS.len() {
+            if counts[index] == 0 { continue; }
+
+            let draw_probability = counts[index] as f64 / remaining as f64;
+            counts[index] -= 1;
+            let drawn = self.distribution(lowest_total + index as u8 + 1, has_ace || index == 0, counts);
+            counts[index] += 1;
+            for (total, total_probability) in distribution.iter_mut().zip(drawn) {
+                *total += draw_probability * total_probability;
+            }
+        }
+        self.distributions.insert(key, distribution);
+        distribution
+    }
+}
+
+/// Like `exact_dealer_totals` from a lone upcard, but given the hole card doesn't make a natural
+fn exact_dealer_totals_without_natural(
+    upcard: &Card,
+    counts: &mut [u32; 10],
+    dealer_totals: &mut [f64; 22],
+    rules: &RulesConfig
+) {
+    let makes_natural = |card: &Card| is_natural(&[upcard.clone(), card.clone()]);
+    let remaining: u32 = RANKS
+        .iter()
+        .zip(counts.iter())
+        .filter(|(card, _)| !makes_natural(card))
+        .map(|(_, count)| count)
+        .sum();
+
+    for (index, card) in RANKS.iter().enumerate() {
+        if counts[index] == 0 || makes_natural(card) { continue; }
+
+        let draw_probability = counts[index] as f64 / remaining as f64;
+        counts[index] -= 1;
+        exact_dealer_totals(&[upcard.clone(), card.clone()], counts, draw_probability, dealer_totals, rules);
+        counts[index] += 1;
+    }
+}
+
+/// Decides whether hitting or standing has the higher EV for a player's hand. The dealer's
+/// final totals are computed exactly from the shoe, but the player's own draws are treated
+/// as coming from fixed rank probabilities (ignoring depletion within the hand) to keep the
+/// recursion small
+struct HitStandSolver {
+    rank_probabilities: [f64; 10],
+    stand_evs: [f64; 22], // EV of standing on each best total
+    /// Decisions already made, by best total and whether it's soft. The solver is built for
+    /// one dealer hand so that completes the key, and a hand's total and softness are all
+    /// its hit EV depends on
+    decisions: std::cell::RefCell<[[Option<bool>; 2]; 22]>,
+    /// Doubling decisions, keyed the same way
+    double_decisions: std::cell::RefCell<[[Option<bool>; 2]; 22]>,
+    /// Whether decisions are kept, which only the tests turn off to check it changes nothing
+    memoise: bool
+}
+
+impl HitStandSolver {
+    /// With `dealer_peeked` the dealer has checked their hole card for a natural and doesn't
+    /// have one, which is how playing decisions are usually quoted. The dealer plays by the
+    /// rules (e.g. hitting soft 17), whose target total must be 21 like DealerTotalsMemo's
+    fn new(deck: &Deck, dealer_cards: &[Card], dealer_peeked: bool, rules: &RulesConfig) -> Self {
+        let mut counts = deck.rank_counts();
+        let remaining: u32 = counts.iter().sum();
+        let rank_probabilities = counts.map(|count| ratio(count as u64, remaining));
+
+        let mut dealer_totals = [0.0; 22];
+        if dealer_peeked && dealer_cards.len() == 1 {
+            exact_dealer_totals_without_natural(&dealer_cards[0], &mut counts, &mut dealer_totals, rules);
+        } else {
+            exact_dealer_totals(dealer_cards, &mut counts, 1.0, &mut dealer_totals, rules);
+        }
+
+        let mut stand_evs = [0.0; 22];
+        for (player_total, stand_ev) in stand_evs.iter_mut().enumerate() {
+            *stand_ev = stand_ev_against(player_total as u8, &dealer_totals);
+        }
+
+        HitStandSolver {
+            rank_probabilities,
+            stand_evs,
+            decisions: Default::default(),
+            double_decisions: Default::default(),
+            memoise: true
+        }
+    }
+
+    /// The solver working out every decision afresh instead of reusing them
+    fn without_memo(self) -> Self {
+        HitStandSolver { memoise: false, ..self }
+    }
+
+    /// Returns true if hitting the hand has a higher EV than standing on it, solving each
+    /// total once and reusing the decision for every later hand on that total
+    fn should_hit(&self, cards: &[Card]) -> bool {
+        let Some(total) = best_hand_total(cards) else { return false };
+        let (total, is_soft) = (total as usize, is_soft_hand(cards) as usize);
+
+        if let Some(decision) = self.decisions.borrow()[total][is_soft] { return decision; }
+        let decision = self.hit_advantage(cards).is_some_and(|advantage| advantage > 0.0);
+        if self.memoise { self.decisions.borrow_mut()[total][is_soft] = Some(decision); }
+        decision
+    }
+
+    /// Returns true if doubling the hand (taking exactly one card for twice the bet) has a
+    /// higher EV than the better of hitting and standing on it
+    fn should_double(&self, cards: &[Card]) -> bool {
+        let Some(total) = best_hand_total(cards) else { return false };
+        let (total, is_soft) = (total as usize, is_soft_hand(cards) as usize);
+
+        if let Some(decision) = self.double_decisions.borrow()[total][is_soft] { return decision; }
+        let best_ev = self.stand_evs[total] + self.hit_advantage(cards).unwrap_or(0.0).max(0.0);
+        let decision = self.double_ev(cards) > best_ev;
+        if self.memoise { self.double_decisions.borrow_mut()[total][is_soft] = Some(decision); }
+        decision
+    }
+
+    /// EV (in original bets) of doubling the hand, which stands on whatever the one card makes
+    fn double_ev(&self, cards: &[Card]) -> f64 {
+        let (hard_total, aces) = hard_total_and_aces(cards);
+        let has_ace = aces > 0;
+        let ev: f64 = self.rank_probabilities
+            .iter()
+            .enumerate()
+            .map(|(index, probability)| {
+                match best_total_from(hard_total + index as u8 + 1, has_ace || index == 0) {
+                    None => -probability,
+                    Some(total) => probability * self.stand_evs[total as usize]
+                }
+            })
+            .sum();
+        2.0 * ev
+    }
+
+    /// How much more (in bets) hitting the hand is worth than standing on it, None if the
+    /// hand is bust or already 21
+    fn hit_advantage(&self, cards: &[Card]) -> Option<f64> {
+        let (hard_total, aces) = hard_total_and_aces(cards);
+        let has_ace = aces > 0;
+        match best_hand_total(cards) {
+            None | Some(21) => None,
+            Some(total) => Some(self.hit_ev(hard_total, has_ace) - self.stand_evs[total as usize])
+        }
+    }
+
+    /// EV of taking a card and then playing optimally, where hard_total counts aces as 1
+    fn hit_ev(&self, hard_total: u8, has_ace: bool) -> f64 {
+        self.rank_probabilities
+            .iter()
+            .enumerate()
+            .filter(|(_, probability)| **probability > 0.0)
+            .map(|(index, probability)| {
+                let hard_total = hard_total + index as u8 + 1; // RANKS are valued 1 (ace) to 10
+                let has_ace = has_ace || index == 0;
+                let ev = match best_total_from(hard_total, has_ace) {
+                    None => -1.0,
+                    Some(21) => self.stand_evs[21], // never worth hitting a 21
+                    Some(total) => self.stand_evs[total as usize].max(self.hit_ev(hard_total, has_ace))
+                };
+                probability * ev
+            })
+            .sum()
+    }
+}
+
+/// Floor on every card's proposal weight relative to its chance of being drawn, which keeps the
+/// importance weights bounded (by (1 + DEFENSIVE_WEIGHT) / DEFENSIVE_WEIGHT) when the
+/// proposal is a poor fit
+const DEFENSIVE_WEIGHT: f64 = 0.1;
+
+/// Importance sampling for the dealer's draws. Rather than drawing each card with its true
+/// chance p, the dealer draws from a defensive mixture where q(card) is proportional to
+/// p(card) * (DEFENSIVE_WEIGHT + reach(next hand)), and reach is the chance the dealer goes on
+/// to beat the player. This over-samples the (possibly rare) draws that beat the player, and
+/// reweighting each simulation by the product of p/q keeps the estimates unbiased
+struct DealerDrawTilt {
+    /// reach[threshold - 17][hard_total][has_ace]: the chance the dealer finishes on at least
+    /// `threshold` from that hand, with the shoe's starting probabilities (i.e. no depletion)
+    reach: [[[f64; 2]; 32]; 6]
+}
+
+impl DealerDrawTilt {
+    fn new(deck: &Deck) -> Self {
+        let counts = deck.rank_counts();
+        let remaining: u32 = counts.iter().sum();
+        let rank_probabilities = counts.map(|count| ratio(count as u64, remaining));
+
+        // thresholds run 17 to 22, where 22 (the player has 21) can never be reached
+        let mut reach = [[[0.0; 2]; 32]; 6];
+        for (offset, table) in reach.iter_mut().enumerate() {
+            let threshold = 17 + offset as u8;
+
+            // drawing only ever increases the hard total, so fill in from the top down
+            for hard_total in (1..32).rev() {
+                for has_ace in [false, true] {
+                    let probability = match best_total_from(hard_total as u8, has_ace) {
+                        None => 0.0,
+                        Some(total) if total >= threshold => 1.0, // thresholds are all 17 or more
+                        Some(total) if total >= 17 => 0.0,
+                        Some(_) => rank_probabilities
+                            .iter()
+                            .enumerate()
+                            .map(|(index, probability)| {
+                                probability * table[hard_total + index + 1][(has_ace || index == 0) as usize]
+                            })
+                            .sum()
+                    };
+                    table[hard_total][has_ace as usize] = probability;
+                }
+            }
+        }
+
+        DealerDrawTilt { reach }
+    }
+
+    /// Draws the dealer's next card from the proposal, returning it with its importance
+    /// weight p/q, or None if the deck is empty
+    fn draw(
+        &self,
+        deck: &mut Deck,
+        dealer_cards: &[Card],
+        player_total: Option<u8>,
+        rng: &mut SimRng
+    ) -> Option<(Card, f64)> {
+        let counts = deck.rank_counts();
+        let remaining: u32 = counts.iter().sum();
+        if remaining == 0 { return None; }
+
+        // the dealer beats the player by finishing above their total, and any finished hand
+        // beats a total under 17. A bust player has already lost, so there is nothing to tilt
+        let threshold = player_total.map_or(22, |total| total.max(16) + 1);
+        let table = &self.reach[(threshold - 17) as usize];
+        let (hard_total, aces) = hard_total_and_aces(dealer_cards);
+        let (hard_total, has_ace) = (hard_total as usize, aces > 0);
+
+        let proposal_weights: [f64; 10] = std::array::from_fn(|index| {
+            let probability = ratio(counts[index] as u64, remaining);
+            probability * (DEFENSIVE_WEIGHT + table[hard_total + index + 1][(has_ace || index == 0) as usize])
+        });
+        let total_proposal_weight: f64 = proposal_weights.iter().sum();
+
+        let mut target = rng.next_unit() * total_proposal_weight;
+        let rank = (0..10)
+            .filter(|&index| proposal_weights[index] > 0.0)
+            .find(|&index| {
+                target -= proposal_weights[index];
+                target < 0.0
+            })
+            .or_else(|| (0..10).rev().find(|&index| proposal_weights[index] > 0.0))?;
+
+        let position = deck.cards.iter().position(|card| card.rank_index() == Some(rank))?;
+        let card = deck.cards.remove(position);
+        let weight = ratio(counts[rank] as u64, remaining) / (proposal_weights[rank] / total_proposal_weight);
+        Some((card, weight))
+    }
+}
+
+/// Draws a card from an infinite deck, where ten-values are 4 of the 13 ranks
+fn draw_from_infinite_deck(rng: &mut SimRng) -> Option<Card> {
+    rng.next_index(13).map(|index| RANKS[index.min(9)].clone())
+}
+
+/// Best total that doesn't bust from a hard total (aces as 1), counting one ace as 11 if it fits
+fn best_total_from(hard_total: u8, has_ace: bool) -> Option<u8> {
+    if has_ace && hard_total + 10 <= 21 {
+        Some(hard_total + 10)
+    } else if hard_total <= 21 {
+        Some(hard_total)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_value_combinations() {
+        let first_values = vec![1, 11];
+        let second_values = vec![7];
+        let third_values = vec![1, 11];
+        let input = vec![first_values, second_values, third_values];
+        let expected = vec![9, 19, 19, 29];
+
+        let mut result = generate_value_combinations(&input);
+        result.sort();
+
+        assert_eq!(expected, result, "Expected {:?} but got {:?}", expected, result);
+    }
+
+    #[test]
+    fn test_evaluate_hand() {
+        let hand = vec![Card::Ace, Card::Five, Card::Three];
+        let expected = vec![9, 19];
+
+        let mut result = evaluate_hand(&hand);
+        result.sort();
+
+        assert_eq!(expected, result, "Expected {:?} but got {:?}", expected, result);
+    }
+
+    #[test]
+    fn test_best_total_matches_the_best_evaluation() {
+        let hands = RANKS.iter().flat_map(|first| RANKS.iter().flat_map(move |second| {
+            RANKS.iter().map(move |third| vec![first.clone(), second.clone(), third.clone(), Card::Ace])
+        }));
+        for hand in hands {
+            for target_total in [21, 31] {
+                let best = evaluate_hand(&hand).into_iter().filter(|&value| value <= target_total).max();
+                assert_eq!(best, best_hand_total_to(&hand, target_total), "{:?} to {:?}", hand, target_total);
+            }
+        }
+    }
+
+    #[test]
+    fn test_best_hand_value() {
+        assert_eq!(12, best_hand_value(vec![Card::Ace, Card::Ace]));
+        assert_eq!(21, best_hand_value(vec![Card::Ace, Card::King]));
+        assert_eq!(25, best_hand_value(vec![Card::King, Card::Queen, Card::Five]));
+        assert_eq!(0, best_hand_value(vec![]));
+    }
+
+    #[test]
+    fn test_cards_sort_by_rank_with_ace_low() {
+        let mut hand = vec![Card::King, Card::Ace, Card::Five];
+        hand.sort();
+
+        assert_eq!(vec![Card::Ace, Card::Five, Card::King], hand);
+        assert!(Card::Empty < Card::Ace);
+        assert!(Card::Ten < Card::Jack);
+    }
+
+    #[test]
+    fn test_evaluate_hand_ignores_empty_mid_hand() {
+        let hand = vec![Card::Ten, Card::Empty, Card::Five];
+
+        assert_eq!(vec![15], evaluate_hand(&hand));
+        assert_eq!(Some(15), best_hand_total(&hand));
+    }
+
+    /// evaluate_hands for a hand of the player's cards against the dealer's under the default rules
+    fn evaluate_cards(players_cards: &[Card], dealers_cards: &[Card]) -> GameOutcome {
+        evaluate_hands(
+            players_cards,
+            is_natural(players_cards),
+            best_hand_total(dealers_cards),
+            is_natural(dealers_cards),
+            &RulesConfig::default()
+        )
+    }
+
+    #[test]
+    fn test_natural_beats_a_three_card_twenty_one() {
+        let natural = vec![Card::Ace, Card::King];
+        let three_sevens = vec![Card::Seven, Card::Seven, Card::Seven];
+        assert_eq!(GameOutcome::WIN, evaluate_cards(&natural, &three_sevens));
+        assert_eq!(GameOutcome::LOSS, evaluate_cards(&three_sevens, &natural));
+        assert_eq!(GameOutcome::TIE, evaluate_cards(&natural, &[Card::Queen, Card::Ace]));
+        assert_eq!(GameOutcome::TIE, evaluate_cards(&three_sevens, &[Card::Ten, Card::Five, Card::Six]));
+
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: natural,
+            dealer_card: three_sevens,
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 100,
+            ..Default::default()
+        };
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!((1.0, 1.0, 0.0), (stand.win, stand.blackjack, stand.tie));
+        assert_eq!(15.0, stand.estimated_value);
+    }
+
+    #[test]
+    fn test_evaluate_hands_keys_naturals_off_the_hand_the_player_started_from() {
+        let rules = RulesConfig { five_card_charlie: true, ..Default::default() };
+        let split_ace_twenty_one = [Card::Ace, Card::King];
+        let dealer_twenty_one = best_hand_total(&[Card::Seven, Card::Seven, Card::Seven]);
+        assert_eq!(GameOutcome::TIE, evaluate_hands(&split_ace_twenty_one, false, dealer_twenty_one, false, &rules));
+        assert_eq!(GameOutcome::WIN, evaluate_hands(&split_ace_twenty_one, true, dealer_twenty_one, false, &rules));
+
+        let charlie = [Card::Two, Card::Two, Card::Three, Card::Three, Card::Four];
+        assert_eq!(GameOutcome::WIN, evaluate_hands(&charlie, false, Some(20), false, &rules));
+        assert_eq!(GameOutcome::LOSS, evaluate_hands(&charlie, false, Some(21), true, &rules));
+    }
+
+    #[test]
+    fn test_evaluate_hands_player_busts() {
+        let player_hand = vec![Card::Jack, Card::Five, Card::Seven];
+        let dealers_hand = vec![Card::Jack, Card::Six, Card::Queen];
+        let expected_outcome = GameOutcome::LOSS;
+
+        let actual_outcome = evaluate_cards(
+            &player_hand,
+            &dealers_hand
+        );
+
+        assert_eq!(expected_outcome, actual_outcome,
+                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+    }
+
+    #[test]
+    fn test_evaluate_hands_player_dealer_busts_player_does_not_bust() {
+        let player_hand = vec![Card::Jack, Card::Five];
+        let dealers_hand = vec![Card::Jack, Card::Six, Card::Queen];
+        let expected_outcome = GameOutcome::WIN;
+
+        let actual_outcome = evaluate_cards(
+            &player_hand,
+            &dealers_hand
+        );
+
+        assert_eq!(expected_outcome, actual_outcome,
+                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+    }
+
+    #[test]
+    fn test_evaluate_hands_when_equal() {
+        let player_hand = vec![Card::Six, Card::Five];
+        let dealers_hand = vec![Card::Six, Card::Five];
+        let expected_outcome = GameOutcome::TIE;
+
+        let actual_outcome = evaluate_cards(
+            &player_hand,
+            &dealers_hand
+        );
+
+        assert_eq!(expected_outcome, actual_outcome,
+                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+    }
+
+    #[test]
+    fn test_evaluate_hands_when_equal_with_aces() {
+        let player_hand = vec![Card::Six, Card::Five, Card::Ace];
+        let dealers_hand = vec![Card::Six, Card::Five, Card::Ace];
+        let expected_outcome = GameOutcome::TIE;
+
+        let actual_outcome = evaluate_cards(
+            &player_hand,
+            &dealers_hand
+        );
+
+        assert_eq!(expected_outcome, actual_outcome,
+                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+    }
+
+    #[test]
+    fn test_generate_hit_outcomes_ten_thousand_sims() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ace, Card::Jack],
+                dealer_card: vec![Card::Six],
+                num_decks: 10,
+                bet_size: 100.0,
+                num_sims: 10_000,
+                ..Default::default()
+            },
+            BlackJackAction::HIT(1)
+        );
+
+        assert_ne!(outcome.win, 1.0);
+        assert_ne!(outcome.win, 0.0);
+
+        assert_ne!(outcome.loss, 1.0);
+        assert_ne!(outcome.loss, 0.0);
+
+        assert_ne!(outcome.tie, 1.0);
+        assert_ne!(outcome.tie, 0.0);
+    }
+
+    #[test]
+    fn test_ev_at_bet_doubling_bet_doubles_hit_ev() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ten, Card::Six],
+                dealer_card: vec![Card::Ten],
+                num_decks: 6,
+                bet_size: 100.0,
+                num_sims: 10_000,
+                ..Default::default()
+            },
+            BlackJackAction::HIT(1)
+        );
+
+        assert_eq!(outcome.estimated_value, outcome.ev_at_bet(100.0));
+        assert_eq!(2.0 * outcome.ev_at_bet(100.0), outcome.ev_at_bet(200.0));
+    }
+
+    #[test]
+    fn test_hit_to_seventeen_from_hard_twelve_draws_more_than_one_card() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ten, Card::Two],
+                dealer_card: vec![Card::Six],
+                num_decks: 6,
+                bet_size: 100.0,
+                num_sims: 10_000,
+                ..Default::default()
+            },
+            BlackJackAction::HIT_TO(17)
+        );
+
+        assert!(outcome.average_cards_drawn > 1.0);
+    }
+
+    #[test]
+    fn test_stand_draws_no_cards() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ten, Card::Two],
+                dealer_card: vec![Card::Six],
+                num_decks: 6,
+                bet_size: 100.0,
+                num_sims: 1_000,
+                ..Default::default()
+            },
+            BlackJackAction::STAND
+        );
+
+        assert_eq!(0.0, outcome.average_cards_drawn);
+    }
+
+    #[test]
+    fn test_double_for_half_ev_between_single_hit_and_full_double() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Six, Card::Five],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 20_000,
+            ..Default::default()
+        };
+
+        let hit_once = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        let double_for_half = action_outcomes.generate_outcomes(&data, BlackJackAction::DOUBLE_FOR(0.5));
+        let full_double = action_outcomes.generate_outcomes(&data, BlackJackAction::DOUBLE_FOR(1.0));
+
+        assert_eq!(1.5, double_for_half.stake);
+        assert!(hit_once.estimated_value < double_for_half.estimated_value,
+                "Expected {:?} < {:?}", hit_once.estimated_value, double_for_half.estimated_value);
+        assert!(double_for_half.estimated_value < full_double.estimated_value,
+                "Expected {:?} < {:?}", double_for_half.estimated_value, full_double.estimated_value);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "known cards must all come out of the shoe")]
+    fn test_invariant_check_catches_more_known_cards_than_shoe_holds() {
+        let action_outcomes = ActionOutcomes::new();
+        action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ace, Card::Ace, Card::Ace, Card::Ace, Card::Ace],
+                dealer_card: vec![Card::Six],
+                num_decks: 1,
+                bet_size: 100.0,
+                num_sims: 10,
+                ..Default::default()
+            },
+            BlackJackAction::STAND
+        );
+    }
+
+    #[test]
+    fn test_resolve_stand_matches_full_computation() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Eight],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 20_000,
+            ..Default::default()
+        };
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&data);
+        let resolution = action_outcomes.generate_stand_resolution(&data).unwrap();
+
+        let difference = (resolution.stand.estimated_value - action_outcomes.stand.estimated_value).abs();
+        assert!(difference < 5.0, "Expected EVs within $5 but they differ by {:?}", difference);
+
+        let bucket_total = |dealer_totals: &DealerTotals| dealer_totals.seventeen + dealer_totals.eighteen
+            + dealer_totals.nineteen + dealer_totals.twenty + dealer_totals.twenty_one + dealer_totals.bust
+            + dealer_totals.under_seventeen;
+        let total = bucket_total(&resolution.dealer_totals);
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+        assert_eq!(0.0, resolution.dealer_totals.under_seventeen);
+
+        // a dealer that stands on 14 often finishes under 17
+        let stand_on_fourteen = UserDataState { rules: RulesConfig { dealer_stand_value: 14, ..Default::default() }, ..data.clone() };
+        let dealer_totals = action_outcomes.generate_stand_resolution(&stand_on_fourteen).unwrap().dealer_totals;
+        let total = bucket_total(&dealer_totals);
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+        assert!(dealer_totals.under_seventeen > 0.3, "Got {:?}", dealer_totals);
+
+        // finishing on 22 to 25 has no bucket, so a game with another target isn't bucketed
+        let twenty_five = UserDataState { rules: RulesConfig { target_total: 25, ..Default::default() }, ..data };
+        let resolution = action_outcomes.generate_stand_resolution(&twenty_five);
+        assert_eq!(Some(SimulationErrorKind::NotApplicable), resolution.err().map(|error| error.kind));
+    }
+
+    #[test]
+    fn test_player_totals_after_hitting_twice() {
+        let data = UserDataState {
+            current_cards: vec![Card::Five, Card::Four],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 20_000,
+            ..Default::default()
+        };
+        let resolution = ActionOutcomes::new().generate_player_totals_resolution(&data, BlackJackAction::HIT(2)).unwrap();
+        let player_totals = resolution.player_totals;
+
+        let total: f64 = player_totals.totals.iter().sum::<f64>() + player_totals.bust;
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+        // 9 can't bust or stay below 11 in two cards (bar a dealer natural stopping it at 9)
+        assert!(player_totals.totals[..5].iter().all(|&probability| probability == 0.0));
+        assert!(player_totals.totals[9 - 4] < 0.001, "Got {:?}", player_totals.totals);
+        assert!(player_totals.bust > 0.0 && player_totals.totals[21 - 4] > 0.0);
+        assert!((resolution.outcomes.win + resolution.outcomes.loss + resolution.outcomes.tie - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bankroll_risk_of_a_short_bankroll() {
+        let data = |player_cards: Vec<Card>| UserDataState {
+            current_cards: player_cards,
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 5_000,
+            seed: Some(527),
+            ..Default::default()
+        };
+        let action_outcomes = ActionOutcomes::new();
+
+        // 12 vs 10 loses every way it's played, so three bets rarely last 200 hands
+        let risk = action_outcomes.generate_bankroll_risk(&data(vec![Card::Ten, Card::Two]), 30.0, 200, 2_000).unwrap();
+        assert!(risk.ruin_probability > 0.8, "Got {:?}", risk);
+        assert!(risk.p5_final_bankroll <= risk.median_final_bankroll && risk.median_final_bankroll <= risk.p95_final_bankroll);
+        assert_eq!(0.0, risk.p5_final_bankroll);
+
+        // 20 vs 10 wins on average, so a deep bankroll almost never goes broke
+        let risk = action_outcomes.generate_bankroll_risk(&data(vec![Card::Ten, Card::King]), 1_000.0, 200, 2_000).unwrap();
+        assert_eq!("stand", risk.action);
+        assert!(risk.ruin_probability < 0.01, "Got {:?}", risk);
+        assert!(risk.median_final_bankroll > 1_000.0, "Got {:?}", risk);
+
+        let kind = |risk: Result<BankrollRisk, SimulationError>| risk.err().map(|error| error.kind);
+        let invalid = Some(SimulationErrorKind::InvalidSettings);
+        assert_eq!(invalid, kind(action_outcomes.generate_bankroll_risk(&data(vec![Card::Ten, Card::King]), 0.0, 200, 2_000)));
+        assert_eq!(invalid, kind(action_outcomes.generate_bankroll_risk(&data(vec![Card::Ten, Card::King]), 100.0, 0, 2_000)));
+    }
+
+    #[test]
+    fn test_natural_outcome_pushes_with_dealer_natural() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ace, Card::King],
+            dealer_card: vec![Card::Ace],
+            num_decks: 1,
+            bet_size: 100.0,
+            num_sims: 1,
+            ..Default::default()
+        };
+        let outcome = exact_natural_outcome(&data).unwrap();
+
+        // one ten and two aces are gone, leaving 15 tens among 49 cards
+        let dealer_natural = 15.0 / 49.0;
+        assert!((outcome.tie - dealer_natural).abs() < 1e-12, "Expected {:?} but got {:?}", dealer_natural, outcome.tie);
+        assert!((outcome.win - (1.0 - dealer_natural)).abs() < 1e-12);
+        assert_eq!(0.0, outcome.loss);
+        assert!((outcome.estimated_value - 150.0 * (1.0 - dealer_natural)).abs() < 1e-9);
+
+        let data = UserDataState { dealer_card: vec![Card::Seven], ..data };
+        assert_eq!(0.0, exact_natural_outcome(&data).unwrap().tie);
+
+        let data = UserDataState { current_cards: vec![Card::Ten, Card::Five, Card::Six], ..data };
+        assert!(exact_natural_outcome(&data).is_none());
+    }
+
+    #[test]
+    fn test_dealer_draws_hard_sixteen_when_ace_as_eleven_busts() {
+        assert!(dealer_should_draw(&[Card::Ace, Card::Five, Card::King]));
+        assert!(!dealer_should_draw(&[Card::Ace, Card::Six]));
+    }
+
+    #[test]
+    fn test_hit_then_optimal_between_triple_hit_and_optimal_play() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Four],
+            dealer_card: vec![Card::Four],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 20_000,
+            ..Default::default()
+        };
+
+        let triple_hit = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(3));
+        let hit_then_optimal = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT_THEN_OPTIMAL(1));
+        let optimal = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT_THEN_OPTIMAL(0));
+
+        assert!(triple_hit.estimated_value < hit_then_optimal.estimated_value,
+                "Expected {:?} < {:?}", triple_hit.estimated_value, hit_then_optimal.estimated_value);
+        assert!(hit_then_optimal.estimated_value < optimal.estimated_value,
+                "Expected {:?} < {:?}", hit_then_optimal.estimated_value, optimal.estimated_value);
+    }
+
+    #[test]
+    fn test_solver_stands_on_hard_twenty_and_hits_hard_eight() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            ..Default::default()
+        };
+        let solver = HitStandSolver::new(&remaining_deck(&data), &data.dealer_card, false, &data.rules);
+
+        assert!(!solver.should_hit(&[Card::Ten, Card::Queen]));
+        assert!(solver.should_hit(&[Card::Five, Card::Three]));
+    }
+
+    #[test]
+    fn test_solver_stands_against_the_dealer_the_rules_describe() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Seven],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            ..Default::default()
+        };
+        let deck = remaining_deck(&data);
+        let standard_solver = HitStandSolver::new(&deck, &data.dealer_card, false, &data.rules);
+        for rules in [RulesConfig { dealer_hits_soft_17: true, ..Default::default() },
+                      RulesConfig { dealer_stand_value: 18, ..Default::default() }] {
+            let solver = HitStandSolver::new(&deck, &data.dealer_card, false, &rules);
+            for player_total in [17, 18] {
+                let hand = [Card::Ten, RANKS[player_total - 11].clone()];
+                let exact = exact_stand_ev(&hand, &data.dealer_card, &mut deck.rank_counts(), &rules);
+                assert!((solver.stand_evs[player_total] - exact).abs() < 1e-12,
+                        "Expected {:?} standing on {:?} under {:?} but got {:?}", exact, player_total, rules, solver.stand_evs[player_total]);
+                assert_ne!(standard_solver.stand_evs[player_total], solver.stand_evs[player_total]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hitting_a_hard_sixteen_busts_often_and_standing_never_does() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 10_000,
+            seed: Some(541),
+            ..Default::default()
+        };
+        assert_eq!(0.0, action_outcomes.generate_outcomes(&data, BlackJackAction::STAND).bust);
+
+        // a six or more busts it, about 8 cards in 13, when the player gets to hit at all (the
+        // dealer's ten makes a natural with the 1 in 13 aces)
+        let hit = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        assert!((hit.bust - 8.0 / 13.0 * 12.0 / 13.0).abs() < 0.03, "Got {:?}", hit.bust);
+        assert!(hit.loss > hit.bust);
+    }
+
+    #[test]
+    fn test_play_optimal_stands_on_hard_twelve_against_a_six() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 5_000,
+            seed: Some(540),
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+
+        // never hitting, it plays out exactly like standing
+        assert_eq!(0.0, action_outcomes.play_optimal.average_cards_drawn);
+        assert_eq!(action_outcomes.stand.estimated_value, action_outcomes.play_optimal.estimated_value);
+        assert_eq!("stand", action_outcomes.best_action());
+
+        // against a three, hitting a hard 12 is narrowly better (as in basic strategy)
+        let against_three = UserDataState { dealer_card: vec![Card::Three], ..data };
+        let play_optimal = action_outcomes.generate_outcomes(&against_three, BlackJackAction::HIT_THEN_OPTIMAL(0));
+        assert!(play_optimal.average_cards_drawn >= 1.0, "Got {:?}", play_optimal);
+    }
+
+    #[test]
+    fn test_generate_stand_outcomes_ten_thousand_sims() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ace, Card::Queen],
+                dealer_card: vec![Card::Six],
+                num_decks: 10,
+                bet_size: 100.0,
+                num_sims: 10_000,
+                ..Default::default()
+            },
+            BlackJackAction::STAND
+        );
+
+        assert!(outcome.win > outcome.loss);
+
+        // a dealer six can't make a natural and the player's natural beats any other 21
+        assert_eq!(outcome.win, 1.0);
+        assert_eq!(outcome.blackjack, 1.0);
+
+        assert_eq!(outcome.loss, 0.0);
+
+        assert_eq!(outcome.tie, 0.0);
+    }
+
+    #[test]
+    fn test_generate_stand_outcomes_expected_loss_ten_thousand_sims() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Two],
+                dealer_card: vec![Card::Six],
+                num_decks: 10,
+                bet_size: 100.0,
+                num_sims: 10_000,
+                ..Default::default()
+            },
+            BlackJackAction::STAND
+        );
+
+        assert!(outcome.loss > outcome.win);
+    }
+
+    #[test]
+    fn test_hand_label_distinguishes_twenty_one_from_blackjack() {
+        assert_eq!("Blackjack", hand_label(vec![Card::Ace, Card::King]));
+        assert_eq!("21", hand_label(vec![Card::Seven, Card::Seven, Card::Seven]));
+        assert_eq!("Soft 17", hand_label(vec![Card::Ace, Card::Six]));
+        assert_eq!("Hard 17", hand_label(vec![Card::Ace, Card::Six, Card::King]));
+        assert_eq!("Bust", hand_label(vec![Card::King, Card::Queen, Card::Five]));
+    }
+
+    #[test]
+    fn test_classify_hand_soft_and_hard_totals() {
+        assert_eq!((17, true), classify_hand(&[Card::Ace, Card::Six]));
+        assert_eq!((17, false), classify_hand(&[Card::Ace, Card::Six, Card::King]));
+        assert_eq!((12, true), classify_hand(&[Card::Ace, Card::Ace]));
+        assert_eq!((25, false), classify_hand(&[Card::King, Card::Queen, Card::Five]));
+    }
+
+    #[test]
+    fn test_five_card_charlie_beats_a_dealer_twenty() {
+        let action_outcomes = ActionOutcomes::new();
+        // four twos can't bust on a fifth card, but can't reach 20 either
+        let data = UserDataState {
+            current_cards: vec![Card::Two; 4],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000,
+            fixed_dealer_total: Some(20),
+            ..Default::default()
+        };
+        let hit = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        assert_eq!(1.0, hit.loss);
+
+        let data = UserDataState { rules: RulesConfig { five_card_charlie: true, ..Default::default() }, ..data };
+        let hit = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        assert_eq!(1.0, hit.win);
+        // standing on four cards isn't a Charlie
+        assert_eq!(1.0, action_outcomes.generate_outcomes(&data, BlackJackAction::STAND).loss);
+        assert!(!is_charlie(&[Card::King, Card::Two, Card::Two, Card::Two, Card::Ten], &data.rules));
+    }
+
+    #[test]
+    fn test_drawing_actions_not_applicable_on_twenty_one() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Seven, Card::Seven, Card::Seven],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        });
+
+        assert!(!action_outcomes.hit_once.applicable);
+        assert!(!action_outcomes.hit_twice.applicable);
+        assert!(!action_outcomes.hit_thrice.applicable);
+        assert!(!action_outcomes.hit_once_then_optimal.applicable);
+        assert!(!action_outcomes.hit_then_double.applicable);
+        assert!(!action_outcomes.double_down.applicable);
+        assert!(!action_outcomes.split_hit_once.applicable);
+        assert!(!action_outcomes.split_hit_twice.applicable);
+        assert!(!action_outcomes.split_hit_thrice.applicable);
+        assert!(action_outcomes.stand.applicable);
+    }
+
+    #[test]
+    fn test_stream_net_results_in_bounded_batches() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        };
+
+        let mut batch_sizes = vec![];
+        action_outcomes.stream_net_results_with(&data, BlackJackAction::HIT(1), 300, &mut |batch| {
+            assert!(batch.iter().all(|net| [-100.0, 0.0, 100.0].contains(net)));
+            batch_sizes.push(batch.len());
+        });
+
+        assert_eq!(vec![300, 300, 300, 100], batch_sizes);
+
+        // every simulation is streamed, even with a target width that would stop the EV early
+        let targeted = UserDataState { num_sims: 5_000, target_ci_width: Some(1_000.0), ..data };
+        let mut num_streamed = 0;
+        action_outcomes.stream_net_results_with(&targeted, BlackJackAction::HIT(1), 300, &mut |batch| num_streamed += batch.len());
+        assert_eq!(5_000, num_streamed);
+        assert_eq!(Some(BlackJackAction::HIT(1)), action_for_label("hit_once"));
+        assert_eq!(None, action_for_label("hit_forever"));
+    }
+
+    #[test]
+    fn test_recommendation_edge_over_mimicking_the_dealer() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 20_000,
+            ..Default::default()
+        };
+        // mimicking the dealer only draws one card to a hard 16, which is also roughly what
+        // the recommendation does, so the edge is positive but small
+        let edge = action_outcomes.generate_recommendation_edge(&data);
+        assert!(edge > -4.0, "Expected a non-negative edge (up to noise) but got {:?}", edge);
+
+        // standing on 12 vs 6 is worth far more than hitting it to 17
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Six],
+            ..data
+        };
+        let edge = action_outcomes.generate_recommendation_edge(&data);
+        assert!(edge > 5.0, "Expected a clearly positive edge but got {:?}", edge);
+    }
+
+    #[test]
+    fn test_modal_totals_when_standing_on_twenty() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 10_000,
+            ..Default::default()
+        };
+        let outcomes = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+
+        assert_eq!(20, outcomes.modal_player_total);
+        assert_eq!(1.0, outcomes.modal_player_total_frequency);
+        assert!((17..=21).contains(&outcomes.modal_dealer_total),
+                "Expected a dealer total of 17-21 but got {:?}", outcomes.modal_dealer_total);
+        assert!(outcomes.modal_dealer_total_frequency > 0.2 && outcomes.modal_dealer_total_frequency < 0.6,
+                "Expected a frequency of 0.2-0.6 but got {:?}", outcomes.modal_dealer_total_frequency);
+    }
+
+    #[test]
+    fn test_natural_pays_more_than_hard_twenty() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ace, Card::King],
+            dealer_card: vec![Card::Nine],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 2_000,
+            ..Default::default()
+        };
+        let natural = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        let hard_twenty = action_outcomes.generate_outcomes(
+            &UserDataState { current_cards: vec![Card::Ten, Card::King], ..data },
+            BlackJackAction::STAND
+        );
+
+        // a natural only pushes a dealer natural, which a nine can't make, and otherwise wins 3:2
+        assert!(natural.estimated_value > hard_twenty.estimated_value);
+        let expected = (natural.blackjack * BLACKJACK_PAYOUT - natural.loss) * 10.0;
+        assert!((natural.estimated_value - expected).abs() < 1e-9);
+        assert!(natural.estimated_value > 13.0, "Expected 15 but got {:?}", natural.estimated_value);
+    }
+
+    #[test]
+    fn test_hitting_stops_once_the_player_busts() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::King],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 2_000,
+            ..Default::default()
+        };
+
+        let mut longest_hand = 0;
+        let hit_thrice = action_outcomes.generate_outcomes_with(&data, BlackJackAction::HIT(3), &mut |cards, _, _| {
+            longest_hand = longest_hand.max(cards.len());
+        });
+
+        // only an ace survives the first card, and 21 busts on the second
+        assert!(longest_hand <= 4, "Drew {:?} cards", longest_hand);
+        assert!(hit_thrice.average_cards_drawn < 1.15, "Got {:?}", hit_thrice.average_cards_drawn);
+    }
+
+    #[test]
+    fn test_draws_from_a_tiny_deck_are_uniform() {
+        let deck = Deck { cards: vec![Card::Two, Card::Five, Card::King] };
+        let mut rng = SimRng::from_seed(Some(508));
+        let mut counts = [0u32; 3];
+        for _ in 0..30_000 {
+            match deck.clone().take_random_card_using(&mut rng) {
+                Some(Card::Two) => counts[0] += 1,
+                Some(Card::Five) => counts[1] += 1,
+                _ => counts[2] += 1
+            }
+        }
+
+        for count in counts {
+            let frequency = count as f64 / 30_000.0;
+            assert!((frequency - 1.0 / 3.0).abs() < 0.015, "Got frequencies {:?}", counts);
+        }
+        assert!((0..1_000).all(|_| rng.next_index(7).is_some_and(|index| index < 7)));
+    }
+
+    #[test]
+    fn test_later_draws_from_a_refilled_deck_are_uniform() {
+        let deck = Deck { cards: vec![Card::Two, Card::Five, Card::King, Card::Ace] };
+        let mut shoe = Deck { cards: Vec::new() };
+        let mut rng = SimRng::from_seed(Some(547));
+        let mut second_draws = [0u32; 4];
+        for _ in 0..40_000 {
+            shoe.refill_from(&deck);
+            let first = shoe.take_random_card_using(&mut rng).unwrap();
+            let second = shoe.take_random_card_using(&mut rng).unwrap();
+            assert_ne!(first, second);
+            assert_eq!(2, shoe.cards.len());
+            second_draws[deck.cards.iter().position(|card| *card == second).unwrap()] += 1;
+        }
+
+        for count in second_draws {
+            let frequency = count as f64 / 40_000.0;
+            assert!((frequency - 0.25).abs() < 0.015, "Got second draws {:?}", second_draws);
+        }
+    }
+
+    #[test]
+    fn test_raw_counts_match_the_probabilities() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Eight, Card::Eight],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 3_000,
+            ..Default::default()
+        };
+
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!(3_000, stand.num_sims);
+        assert_eq!(stand.num_sims, stand.wins + stand.losses + stand.ties);
+        assert_eq!(stand.win, stand.wins as f64 / 3_000.0);
+        assert_eq!(stand.loss, stand.losses as f64 / 3_000.0);
+
+        // two hands are counted for every split (a six can't have a natural that ends it early)
+        let split = action_outcomes.generate_outcomes(&data, BlackJackAction::SPLIT(1));
+        assert_eq!(2 * split.num_sims, split.wins + split.losses + split.ties);
+        assert_eq!(split.win, split.wins as f64 / 6_000.0);
+    }
+
+    #[test]
+    fn test_infinite_deck_draws_tens_four_times_in_thirteen() {
+        let mut rng = SimRng::from_seed(Some(519));
+        let tens = (0..130_000)
+            .filter(|_| draw_from_infinite_deck(&mut rng) == Some(Card::Ten))
+            .count();
+        let frequency = tens as f64 / 130_000.0;
+        assert!((frequency - 4.0 / 13.0).abs() < 0.005, "Got {:?}", frequency);
+
+        // hitting 16 busts on a six or more, with nothing depleted by the cards already seen
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 1,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(519),
+            rules: RulesConfig { infinite_deck: true, ..Default::default() },
+            ..Default::default()
+        };
+        let mut busts = 0;
+        let hit = action_outcomes.generate_outcomes_with(&data, BlackJackAction::HIT(1), &mut |cards, _, _| {
+            if best_hand_total(cards).is_none() { busts += 1; }
+        });
+        // hands that never drew (dealer naturals) can't bust
+        let bust_frequency = busts as f64 / (20_000.0 * (1.0 - 1.0 / 13.0));
+        assert!((bust_frequency - 8.0 / 13.0).abs() < 0.015, "Got {:?}", bust_frequency);
+        assert!((hit.win + hit.loss + hit.tie - 1.0).abs() < 1e-9);
+        assert_eq!(0, hit.discarded_sims);
+    }
+
+    #[test]
+    fn test_seeded_runs_are_identical() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000,
+            seed: Some(473),
+            ..Default::default()
+        };
+
+        let first = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        let second = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        assert_eq!(first, second);
+
+        let other_seed = action_outcomes.generate_outcomes(&UserDataState { seed: Some(474), ..data }, BlackJackAction::HIT(1));
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn test_explicit_seed_reproduces_every_action() {
+        let data = || parse_state(UserDataStateHolder::new(
+            vec![Card::Eight, Card::Eight],
+            vec![Card::Nine],
+            "6".to_string(),
+            "10".to_string(),
+            "1000".to_string()
+        )).unwrap();
+        let outcomes_for = |data: UserDataState| {
+            let mut action_outcomes = ActionOutcomes::new();
+            action_outcomes.populate_action_outcomes(&data);
+            action_outcomes
+        };
+
+        let first = outcomes_for(data().with_seed(Some(552)));
+        assert_eq!(first, outcomes_for(data().with_seed(Some(552))));
+        assert_ne!(first, outcomes_for(data().with_seed(Some(553))));
+
+        // no explicit seed keeps the one already set
+        let preset = UserDataState { seed: Some(552), ..data() };
+        assert_eq!(Some(552), preset.with_seed(None).seed);
+        assert_eq!(None, data().with_seed(None).seed);
+    }
+
+    #[test]
+    fn test_common_random_numbers_reduce_the_variance_of_ev_differences() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 1.0,
+            num_sims: 2_000,
+            ..Default::default()
+        };
+        let action_outcomes = ActionOutcomes::new();
+        let ev_with_seed = |action: BlackJackAction, seed: u64| {
+            action_outcomes.generate_outcomes(&UserDataState { seed: Some(seed), ..data.clone() }, action).ev_per_bet
+        };
+        let variance = |differences: &[f64]| {
+            let mean = differences.iter().sum::<f64>() / differences.len() as f64;
+            differences.iter().map(|difference| (difference - mean).powi(2)).sum::<f64>() / (differences.len() - 1) as f64
+        };
+
+        // hitting 12 against a 6 compared to standing, from a shared seed and from unrelated ones
+        let (shared, independent): (Vec<f64>, Vec<f64>) = (0..40u64)
+            .map(|seed| {
+                let stand = ev_with_seed(BlackJackAction::STAND, seed);
+                (ev_with_seed(BlackJackAction::HIT(1), seed) - stand,
+                 ev_with_seed(BlackJackAction::HIT(1), seed + 1_000) - stand)
+            })
+            .unzip();
+        assert!(variance(&shared) * 1.5 < variance(&independent),
+                "shared {} vs independent {}", variance(&shared), variance(&independent));
+    }
+
+    #[test]
+    fn test_dealer_busts_more_often_hitting_soft_17() {
+        let s17 = RulesConfig::default();
+        let h17 = RulesConfig { dealer_hits_soft_17: true, ..Default::default() };
+        assert!(!dealer_should_draw_under(&[Card::Ace, Card::Six], &s17));
+        assert!(dealer_should_draw_under(&[Card::Ace, Card::Six], &h17));
+        assert!(!dealer_should_draw_under(&[Card::Ten, Card::Seven], &h17));
+        assert!(!dealer_should_draw_under(&[Card::Ace, Card::Six, Card::Ten], &h17), "hard 17 stands");
+
+        let mut deck = Deck::new(&6);
+        deck.remove_card_from_deck(&Card::Six);
+        let bust_rate = |rules: &RulesConfig| {
+            let busts = (0..40_000)
+                .filter(|_| {
+                    let mut shoe = deck.clone();
+                    let mut dealer_cards = vec![Card::Six];
+                    handle_dealer_action(&mut dealer_cards, &mut || shoe.take_random_card_from_deck(), rules);
+                    best_hand_total(&dealer_cards).is_none()
+                })
+                .count();
+            busts as f64 / 40_000.0
+        };
+
+        // about 42% under S17 and 44% under H17
+        let (s17_busts, h17_busts) = (bust_rate(&s17), bust_rate(&h17));
+        assert!(h17_busts > s17_busts, "{:?} under H17 vs {:?} under S17", h17_busts, s17_busts);
+        assert!((s17_busts - 0.42).abs() < 0.02, "Got {:?}", s17_busts);
+    }
+
+    #[test]
+    fn test_dealer_busts_far_more_often_showing_a_six_than_a_ten() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(542),
+            ..Default::default()
+        };
+        // about 42% against 21%
+        let six = simulate_dealer_bust(&data);
+        let ten = simulate_dealer_bust(&UserDataState { dealer_card: vec![Card::Ten], ..data });
+        assert!((six - 0.42).abs() < 0.02, "Got {:?}", six);
+        assert!(six > ten + 0.15, "Got {:?} showing a six and {:?} showing a ten", six, ten);
+    }
+
+    #[test]
+    fn test_dealer_busts_more_often_standing_on_a_higher_total() {
+        let stand_on = |dealer_stand_value: u8| RulesConfig { dealer_stand_value, ..Default::default() };
+        assert!(!dealer_should_draw_under(&[Card::Ten, Card::Six], &stand_on(16)));
+        assert!(dealer_should_draw_under(&[Card::Ten, Card::Seven], &stand_on(18)));
+        assert!(!dealer_should_draw_under(&[Card::Ten, Card::Eight], &stand_on(18)));
+        let soft_18 = RulesConfig { dealer_hits_soft_17: true, ..stand_on(18) };
+        assert!(dealer_should_draw_under(&[Card::Ace, Card::Seven], &soft_18));
+
+        let mut rng = SimRng::from_seed(Some(538));
+        let bust_rate = |rules: &RulesConfig, rng: &mut SimRng| {
+            let busts = (0..20_000)
+                .filter(|_| {
+                    let mut shoe = Deck::new(&6);
+                    let mut dealer_cards = vec![Card::Ten];
+                    handle_dealer_action(&mut dealer_cards, &mut || shoe.take_random_card_using(rng), rules);
+                    best_hand_total(&dealer_cards).is_none()
+                })
+                .count();
+            busts as f64 / 20_000.0
+        };
+        let (sixteen_busts, eighteen_busts) = (bust_rate(&stand_on(16), &mut rng), bust_rate(&stand_on(18), &mut rng));
+        assert!(eighteen_busts > sixteen_busts + 0.05, "{:?} standing on 18 vs {:?} on 16", eighteen_busts, sixteen_busts);
+
+        assert_eq!(Err(InvalidStateError::DealerStandValueOutOfRange(22)), stand_on(22).validate());
+        assert_eq!(Ok(()), stand_on(12).validate());
+    }
+
+    #[test]
+    fn test_rules_config_sets_the_blackjack_payout() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ace, Card::King],
+            dealer_card: vec![Card::Nine],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000,
+            rules: RulesConfig { blackjack_payout: 1.2, ..Default::default() },
+            ..Default::default()
+        };
+        let six_to_five = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+
+        let expected = (six_to_five.blackjack * 1.2 - six_to_five.loss) * 10.0;
+        assert!((six_to_five.estimated_value - expected).abs() < 1e-9);
+        assert_eq!(RulesConfig::new(), RulesConfig { blackjack_payout: 1.5, penetration: 0.75, ..data.rules });
+
+        let no_penetration = RulesConfig { penetration: 0.0, ..Default::default() };
+        assert_eq!(Err(InvalidStateError::PenetrationOutOfRange(0.0)), no_penetration.validate());
+        let negative_payout = UserDataState { rules: RulesConfig { blackjack_payout: -1.0, ..Default::default() }, ..data };
+        assert_eq!(Err(InvalidStateError::InvalidBlackjackPayout(-1.0)), negative_payout.validate());
+    }
+
+    #[test]
+    fn test_even_money_is_declined_at_three_to_two() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ace, Card::King],
+            dealer_card: vec![Card::Ace],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(537),
+            ..Default::default()
+        };
+
+        // 95 of the 309 cards left are ten-valued, under the 1 in 3 where even money pays
+        let decision = action_outcomes.generate_even_money(&data).unwrap();
+        assert!((decision.dealer_natural_probability - 95.0 / 309.0).abs() < 0.01, "Got {:?}", decision);
+        assert_eq!(10.0, decision.even_money_ev);
+        assert!(!decision.take_even_money && decision.decline_ev > 10.0, "Got {:?}", decision);
+
+        // a 6:5 natural is worth less than a sure win of the bet
+        let six_to_five = UserDataState { rules: RulesConfig { blackjack_payout: 1.2, ..Default::default() }, ..data };
+        assert!(action_outcomes.generate_even_money(&six_to_five).unwrap().take_even_money);
+
+        let not_natural = UserDataState { current_cards: vec![Card::Ten, Card::King], ..six_to_five };
+        let not_offered = action_outcomes.generate_even_money(&not_natural);
+        assert_eq!(Some(SimulationErrorKind::NotApplicable), not_offered.err().map(|error| error.kind));
+    }
+
+    #[test]
+    fn test_custom_deck_of_tens_and_aces() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Ten],
+            dealer_card: vec![Card::Ace],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(544),
+            ..Default::default()
+        };
+        let standard = action_outcomes.generate_insurance_outcome(&data);
+
+        let tens_and_aces = [vec![Card::Ten; 20], vec![Card::Ace; 20]].concat();
+        let custom = UserDataState { custom_deck: tens_and_aces, ..data };
+        assert_eq!(37, remaining_deck(&custom).cards.len());
+        let custom_insurance = action_outcomes.generate_insurance_outcome(&custom);
+
+        // the dealer's hole card is a ten 18 times in 37 rather than about 94 in 309
+        assert!((custom_insurance.win - 18.0 / 37.0).abs() < 0.02, "Got {:?}", custom_insurance.win);
+        assert!((standard.win - 94.0 / 309.0).abs() < 0.02, "Got {:?}", standard.win);
+
+        let no_fives = UserDataState { current_cards: vec![Card::Five, Card::Ten], ..custom.clone() };
+        assert_eq!(Err(InvalidStateError::MoreCardsThanTheShoe(Card::Five, 1)), no_fives.validate());
+        let nothing_left = UserDataState { custom_deck: vec![Card::Ten, Card::Ten, Card::Ace], ..custom };
+        assert_eq!(Err(InvalidStateError::NoCardsLeft), nothing_left.validate());
+    }
+
+    #[test]
+    fn test_other_players_take_cards_before_the_player_draws() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 1,
+            bet_size: 10.0,
+            num_sims: 1,
+            num_other_players: 6,
+            ..Default::default()
+        };
+        let mut rng = SimRng::from_seed(Some(549));
+
+        // two cards to each of six other seats leaves 37 of the 49 cards for the round
+        let mut shoe = remaining_deck(&data);
+        assert_eq!(12, deal_other_seats(&mut shoe, data.num_other_players, &mut rng));
+        assert_eq!(37, shoe.cards.len());
+
+        // a nearly empty shoe keeps enough back for the player and dealer to finish
+        let mut short_shoe = Deck { cards: vec![Card::Five; 20] };
+        assert_eq!(4, deal_other_seats(&mut short_shoe, data.num_other_players, &mut rng));
+        assert_eq!(OTHER_SEATS_RESERVE, short_shoe.cards.len());
+
+        let crowded = UserDataState { num_other_players: 7, ..data };
+        assert_eq!(Err(InvalidStateError::TooManyOtherPlayers(7)), crowded.validate());
+    }
+
+    #[test]
+    fn test_insurance_accounts_for_visible_tens() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Jack, Card::Queen, Card::King],
+            dealer_card: vec![Card::Ace],
+            num_decks: 1,
+            bet_size: 10.0,
+            num_sims: 10_000,
+            ..Default::default()
+        };
+        let insurance = action_outcomes.generate_insurance_outcome(&data);
+
+        // 12 of the 47 unseen cards are ten-valued
+        let expected = 12.0 / 47.0;
+        assert!((insurance.win - expected).abs() < 0.02, "Expected {:?} but got {:?}", expected, insurance.win);
+        assert!((insurance.estimated_value - 5.0 * (3.0 * insurance.win - 1.0)).abs() < 1e-9);
+        assert!(insurance.estimated_value < 0.0);
+
+        let data = UserDataState { dealer_card: vec![Card::Ten], ..data };
+        let insurance = action_outcomes.generate_insurance_outcome(&data);
+        assert!(!insurance.applicable);
+        assert_eq!((0.0, 0.0, 0.0), (insurance.estimated_value, insurance.win, insurance.loss));
+    }
+
+    #[test]
+    fn test_surrender_always_loses_half_the_bet() {
+        let mut action_outcomes = ActionOutcomes::new();
+        // a seven can't make a natural, which would take the whole bet before surrendering
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 500,
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+
+        let surrender = &action_outcomes.surrender;
+        assert_eq!(-5.0, surrender.estimated_value);
+        assert_eq!((0.0, 1.0, 0.0), (surrender.win, surrender.loss, surrender.tie));
+        assert_eq!(0.0, surrender.unit_variance());
+
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Three, Card::Three],
+            ..data
+        });
+        assert!(!action_outcomes.surrender.applicable);
+    }
+
+    #[test]
+    fn test_double_down_takes_one_card_for_twice_the_bet() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Six, Card::Five],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 2_000,
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+
+        let double_down = &action_outcomes.double_down;
+        assert!(double_down.applicable);
+        assert_eq!(2.0, double_down.stake);
+        assert_eq!(1.0, double_down.average_cards_drawn);
+        assert!((double_down.estimated_value - 2.0 * (double_down.win - double_down.loss) * 10.0).abs() < 1e-9);
+        assert!(double_down.estimated_value > action_outcomes.hit_once.estimated_value);
+
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Three, Card::Three, Card::Five],
+            ..data
+        });
+        assert_eq!(
+            Some("doubling is only offered on the first two cards".to_string()),
+            action_outcomes.double_down.reason
+        );
+    }
+
+    #[test]
+    fn test_hit_then_double_stakes_the_doubled_bet() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Nine],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 10_000,
+            rules: RulesConfig { double_after_hit: true, ..RulesConfig::new() },
+            ..Default::default()
+        };
+        let outcomes = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT_THEN_DOUBLE);
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+
+        // the hands that survive the first card (20 or 21) mostly bust on the doubled card,
+        // so settling them at two bets loses more than a single bet would
+        let single_stake_ev = (outcomes.win - outcomes.loss) * 100.0;
+        assert!(outcomes.estimated_value < single_stake_ev,
+                "Expected {:?} < {:?}", outcomes.estimated_value, single_stake_ev);
+        assert!(outcomes.estimated_value >= -200.0);
+        assert_eq!(2.0, outcomes.stake);
+        assert!(outcomes.estimated_value < stand.estimated_value,
+                "Expected {:?} < {:?}", outcomes.estimated_value, stand.estimated_value);
+
+        // it isn't offered under the default rules
+        let mut all_outcomes = ActionOutcomes::new();
+        all_outcomes.populate_action_outcomes(&UserDataState { rules: RulesConfig::new(), num_sims: 100, ..data });
+        assert_eq!(Some("doubling after hitting is not allowed".to_string()), all_outcomes.hit_then_double.reason);
+    }
+
+    #[test]
+    fn test_percent_mode_scales_probabilities_by_one_hundred() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        });
+        let (win, loss, tie) = (action_outcomes.stand.win, action_outcomes.stand.loss, action_outcomes.stand.tie);
+        let estimated_value = action_outcomes.stand.estimated_value;
+
+        action_outcomes.apply_output_units();
+        assert_eq!(win, action_outcomes.stand.win, "fraction mode is the default");
+
+        action_outcomes.set_as_percent(true);
+        action_outcomes.apply_output_units();
+        assert_eq!(win * 100.0, action_outcomes.stand.win);
+        assert_eq!(loss * 100.0, action_outcomes.stand.loss);
+        assert_eq!(tie * 100.0, action_outcomes.stand.tie);
+        assert_eq!(estimated_value, action_outcomes.stand.estimated_value);
+
+        // the mode is a setting, so it survives clearing the results
+        action_outcomes.clear();
+        assert!(action_outcomes.as_percent);
+    }
+
+    #[test]
+    fn test_standing_on_nineteen_loses_when_dealer_always_makes_twenty() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Nine],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            fixed_dealer_total: Some(20),
+            ..Default::default()
+        };
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+
+        assert_eq!(1.0, stand.loss);
+        assert_eq!(-100.0, stand.estimated_value);
+        assert_eq!(20, stand.modal_dealer_total);
+
+        let data = UserDataState { fixed_dealer_total: Some(12), ..data };
+        assert_eq!(Err(InvalidStateError::FixedDealerTotalOutOfRange(12)), data.validate());
+    }
+
+    #[test]
+    fn test_session_results_mean_is_hands_times_hand_ev() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 20_000,
+            ..Default::default()
+        };
+        let results = action_outcomes.generate_session_results(&data, 10, 2_000).unwrap();
+        let hand_ev = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND).estimated_value;
+
+        assert_eq!("stand", results.action);
+        let total_probability: f64 = results.buckets.iter().map(|bucket| bucket.probability).sum();
+        assert!((total_probability - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total_probability);
+        assert!(results.buckets.windows(2).all(|pair| pair[0].net_units < pair[1].net_units));
+        assert!((results.mean - 10.0 * hand_ev).abs() < 40.0,
+                "Expected about {:?} but got {:?}", 10.0 * hand_ev, results.mean);
+
+        // a natural against a six always pays 3:2, which mustn't round up to 2 in the mean
+        let natural = UserDataState { current_cards: vec![Card::Ace, Card::King], ..data.clone() };
+        let results = action_outcomes.generate_session_results(&natural, 1, 2_000).unwrap();
+        assert_eq!(150.0, results.mean);
+        assert_eq!(2_000, results.num_sessions);
+
+        // a target width only stops the pilot early, the sessions are still all played
+        let targeted = UserDataState { target_ci_width: Some(1_000.0), ..data.clone() };
+        let results = action_outcomes.generate_session_results(&targeted, 10, 2_000).unwrap();
+        let total_probability: f64 = results.buckets.iter().map(|bucket| bucket.probability).sum();
+        assert!((total_probability - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total_probability);
+
+        let no_hands = action_outcomes.generate_session_results(&data, 0, 2_000);
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), no_hands.err().map(|error| error.kind));
+    }
+
+    #[test]
+    fn test_deck_empty_policy_when_the_shoe_runs_out() {
+        let mut deck = Deck::new(&1);
+        let mut player_cards = vec![];
+        while let Some(card) = deck.take_random_card_from_deck() {
+            if card != Card::Two { player_cards.push(card); }
+        }
+        assert_eq!(None, deck.take_random_card_from_deck());
+
+        // the dealer shows one of the twos, leaving only three twos for them to draw to 8
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: player_cards,
+            dealer_card: vec![Card::Two],
+            num_decks: 1,
+            bet_size: 100.0,
+            num_sims: 100,
+            ..Default::default()
+        };
+        let treat_as_stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!(0, treat_as_stand.discarded_sims);
+        assert_eq!(1.0, treat_as_stand.loss + treat_as_stand.win + treat_as_stand.tie);
+
+        let data = UserDataState { on_deck_empty: DeckEmptyPolicy::DiscardSim, ..data };
+        let discard = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!(100, discard.discarded_sims);
+        assert_eq!(0.0, discard.loss + discard.win + discard.tie);
+    }
+
+    #[test]
+    fn test_importance_sampling_reduces_variance_of_rare_loss() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        };
+        let loss_estimates = |importance_sampling: bool| -> Vec<f64> {
+            let data = UserDataState { importance_sampling, ..data.clone() };
+            (0..20)
+                .map(|_| action_outcomes.generate_outcomes(&data, BlackJackAction::STAND).loss)
+                .collect()
+        };
+        let mean_and_variance = |estimates: &[f64]| {
+            let mean = estimates.iter().sum::<f64>() / estimates.len() as f64;
+            let variance = estimates.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (estimates.len() - 1) as f64;
+            (mean, variance)
+        };
+
+        let (plain_mean, plain_variance) = mean_and_variance(&loss_estimates(false));
+        let (sampled_mean, sampled_variance) = mean_and_variance(&loss_estimates(true));
+
+        assert!((plain_mean - sampled_mean).abs() < 0.03,
+                "Expected similar loss probabilities but got {:?} and {:?}", plain_mean, sampled_mean);
+        assert!(sampled_variance / sampled_mean.powi(2) < plain_variance / plain_mean.powi(2),
+                "Expected a lower relative variance but got {:?} vs {:?}", sampled_variance, plain_variance);
+    }
+
+    #[test]
+    fn test_exact_stand_and_hit_match_a_long_simulation() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Nine],
+            num_decks: 1,
+            bet_size: 1.0,
+            num_sims: 200_000,
+            seed: Some(545),
+            ..Default::default()
+        };
+        let exact_data = UserDataState { exact_enumeration: true, ..data.clone() };
+
+        for hits in [0, 1] {
+            let action = || if hits == 0 { BlackJackAction::STAND } else { BlackJackAction::HIT(hits) };
+            let exact = action_outcomes.generate_outcomes(&exact_data, action());
+            let simulated = action_outcomes.generate_outcomes(&data, action());
+
+            assert!(exact.exact && !simulated.exact);
+            assert!((exact.win + exact.loss + exact.tie - 1.0).abs() < 1e-9, "probabilities must sum to 1 after {:?} hits", hits);
+            let tolerance = 4.0 * simulated.std_error.unwrap();
+            assert!((exact.ev_per_bet - simulated.ev_per_bet).abs() < tolerance,
+                    "Expected EVs after {:?} hits within {:?} but got {:?} exactly and {:?} simulated",
+                    hits, tolerance, exact.ev_per_bet, simulated.ev_per_bet);
+            assert!((exact.bust - simulated.bust).abs() < 0.005);
+        }
+
+        // a dealer natural given as two known cards loses before the player can draw
+        let dealer_natural = UserDataState { dealer_card: vec![Card::Ten, Card::Ace], num_sims: 1_000, ..data };
+        let exact_natural = UserDataState { exact_enumeration: true, ..dealer_natural.clone() };
+        for hits in [0, 1] {
+            let action = || if hits == 0 { BlackJackAction::STAND } else { BlackJackAction::HIT(hits) };
+            let exact = action_outcomes.generate_outcomes(&exact_natural, action());
+            let simulated = action_outcomes.generate_outcomes(&dealer_natural, action());
+            assert!(exact.exact);
+            assert_eq!((1.0, 0.0, 0.0), (exact.loss, exact.tie, exact.average_cards_drawn));
+            assert_eq!((simulated.loss, simulated.ev_per_bet, simulated.average_cards_drawn),
+                       (exact.loss, exact.ev_per_bet, exact.average_cards_drawn));
+        }
+    }
+
+    #[test]
+    fn test_low_variance_stand_converges_before_the_cap() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000_000,
+            target_ci_width: Some(0.2),
+            seed: Some(555),
+            ..Default::default()
+        };
+
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert!(stand.num_sims < 100_000, "Expected to stop early but ran {:?}", stand.num_sims);
+        assert!(stand.num_sims.is_multiple_of(CONVERGENCE_CHECK_SIMS));
+        assert!(1.96 * stand.std_error.unwrap() < 0.2);
+
+        let negative = UserDataState { target_ci_width: Some(-1.0), ..data };
+        assert_eq!(Err(InvalidStateError::TargetCiWidthOutOfRange(-1.0)), negative.validate());
+    }
+
+    #[test]
+    fn test_all_actions_negative_for_hard_sixteen_vs_ten() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 5_000,
+            ..Default::default()
+        });
+
+        assert!(action_outcomes.all_actions_negative());
+        assert!(action_outcomes.note.is_some());
+
+        action_outcomes.stand.estimated_value = 1.0;
+        assert!(!action_outcomes.all_actions_negative());
+        assert_eq!(None, action_outcomes.recommendation_note());
+    }
+
+    #[test]
+    fn test_target_total_of_twenty_five() {
+        let hand = [Card::Ten, Card::Nine, Card::Five];
+        assert_eq!(None, best_hand_total(&hand));
+        assert_eq!(Some(24), best_hand_total_to(&hand, 25));
+
+        // the dealer stands on 21 or more rather than 17 or more
+        assert!(dealer_should_draw_to(&[Card::Ten, Card::Eight], 25));
+        assert!(!dealer_should_draw_to(&[Card::Ten, Card::Ace], 25));
+
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: hand.to_vec(),
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 2_000,
+            rules: RulesConfig { target_total: 25, ..RulesConfig::new() },
+            ..Default::default()
+        };
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+
+        assert_eq!(24, stand.modal_player_total);
+        assert!(stand.modal_dealer_total == 0 || stand.modal_dealer_total >= 21,
+                "Expected a dealer total of 21-25 or a bust but got {:?}", stand.modal_dealer_total);
+        assert!(stand.win > 0.5, "Expected 24 to usually win but got {:?}", stand.win);
+
+        let data = UserDataState { rules: RulesConfig { target_total: 40, ..RulesConfig::new() }, ..data };
+        assert_eq!(Err(InvalidStateError::TargetTotalOutOfRange(40)), data.validate());
+    }
+
+    #[test]
+    fn test_clear_cut_spot_needs_far_fewer_sims_to_separate() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = |player_cards: Vec<Card>, dealer_card: Card| UserDataState {
+            current_cards: player_cards,
+            dealer_card: vec![dealer_card],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1,
+            ..Default::default()
+        };
+
+        // standing on 19 is far better than anything else, while 16 vs 10 is close to a coin flip
+        let clear_cut = action_outcomes
+            .estimate_sims_to_separate_top_two(&data(vec![Card::Ten, Card::Nine], Card::Six), 0.95)
+            .unwrap();
+        let near_tie = action_outcomes
+            .estimate_sims_to_separate_top_two(&data(vec![Card::Ten, Card::Six], Card::Ten), 0.95)
+            .unwrap();
+        assert!(clear_cut * 10 < near_tie, "{:?} vs {:?}", clear_cut, near_tie);
+
+        assert!((normal_quantile(0.95) - 1.645).abs() < 1e-3);
+        let certain = action_outcomes.estimate_sims_to_separate_top_two(&data(vec![Card::Ten, Card::Six], Card::Ten), 1.0);
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), certain.err().map(|error| error.kind));
+    }
+
+    /// A seeded run of optimal play, with or without the solver's memo
+    fn optimal_play_outcomes(num_sims: u32, memoise_solver: bool) -> ProbabilityValueOutcomes {
+        let data = UserDataState {
+            current_cards: vec![Card::Five, Card::Four],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims,
+            seed: Some(449),
+            ..Default::default()
+        };
+        ActionOutcomes::new().generate_outcomes_with_solver(
+            &data,
+            BlackJackAction::HIT_THEN_OPTIMAL(0),
+            &mut |_, _, _| {},
+            0,
+            &mut |_| ControlFlow::Continue(()),
+            memoise_solver
+        )
+    }
+
+    #[test]
+    fn test_memoized_solver_gives_the_same_ev_as_solving_every_decision() {
+        assert_eq!(optimal_play_outcomes(5_000, false), optimal_play_outcomes(5_000, true));
+    }
+
+    /// Like-for-like timing of 100k simulations, ignored as wall-clock times aren't reliable
+    /// under load or in debug builds. Run with `cargo test --release -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_memoized_solver_is_faster_on_a_hundred_thousand_sims() {
+        let time = |memoise_solver: bool| {
+            let start = std::time::Instant::now();
+            let outcomes = optimal_play_outcomes(100_000, memoise_solver);
+            (start.elapsed(), outcomes)
+        };
+        let (uncached_time, uncached) = time(false);
+        let (cached_time, cached) = time(true);
+
+        assert_eq!(uncached, cached);
+        assert!(cached_time < uncached_time, "{:?} cached vs {:?} uncached", cached_time, uncached_time);
+    }
+
+    #[test]
+    fn test_single_sim_has_no_std_error() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1,
+            ..Default::default()
+        };
+        let hit = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        assert_eq!(None, hit.std_error);
+        assert!(hit.estimated_value.is_finite() && hit.ev_per_bet.is_finite());
+        assert_eq!((0.0, 0.0), (hit.win_std_error, hit.loss_std_error), "one sim either won or it didn't");
+
+        let data = UserDataState { num_sims: 2_000, ..data };
+        let hit = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        // each hand nets +-10 dollars, so the error is about 10 / sqrt(2000)
+        let std_error = hit.std_error.unwrap();
+        assert!((0.1..0.3).contains(&std_error), "Expected about 0.22 but got {:?}", std_error);
+        let expected_win_std_error = (hit.win * (1.0 - hit.win) / 2_000.0).sqrt();
+        assert!((hit.win_std_error - expected_win_std_error).abs() < 1e-12);
+        assert!(hit.loss_std_error > 0.0 && hit.loss_std_error < 0.012, "Got {:?}", hit.loss_std_error);
+    }
+
+    #[test]
+    fn test_deviation_indices_match_the_classic_numbers() {
+        let data = |player_cards: Vec<Card>, dealer_card: Card| UserDataState {
+            current_cards: player_cards,
+            dealer_card: vec![dealer_card],
+            num_decks: 6,
+            num_sims: 1,
+            ..Default::default()
+        };
+
+        let sixteen_vs_ten = find_deviation_index(&data(vec![Card::Ten, Card::Six], Card::Ten)).unwrap();
+        assert!(sixteen_vs_ten.abs() < 1.5, "Expected about 0 but got {:?}", sixteen_vs_ten);
+
+        let twelve_vs_three = find_deviation_index(&data(vec![Card::Ten, Card::Two], Card::Three)).unwrap();
+        assert!((0.5..5.0).contains(&twelve_vs_three), "Expected about +2 but got {:?}", twelve_vs_three);
+    }
+
+    #[test]
+    fn test_textbook_strategy_ev_is_near_the_house_edge() {
+        // six decks, dealer stands on soft 17, 3:2 naturals, double after split: about -0.5%
+        let rules = RulesConfig { double_after_split: true, ..Default::default() };
+        let ev = simulate_strategy_rounds(&StrategyTable::textbook(), &rules, 6, 100_000);
+        assert!((-0.025..0.015).contains(&ev), "Expected roughly -0.005 but got {:?}", ev);
+
+        let hard = BASIC_STRATEGY_HARD.iter().map(|row| row.to_string()).collect();
+        let soft = BASIC_STRATEGY_SOFT.iter().map(|row| row.to_string()).collect();
+        let pairs = vec!["PPPPPPPPPP".to_string(); 9];
+        let missing_pair_row = StrategyTable::from_rows(hard, soft, pairs);
+        assert_eq!(Some(SimulationErrorKind::InvalidStrategy), missing_pair_row.err().map(|error| error.kind),
+                   "a missing pair row must be rejected");
+    }
+
+    #[test]
+    fn test_house_edge_for_standard_six_deck_rules() {
+        // six decks, S17, DAS, 3:2 and a peek is about 0.4% with no surrender
+        let rules = RulesConfig { double_after_split: true, ..Default::default() };
+        let edge = house_edge(&rules, 6, 400_000).unwrap();
+        assert!((-0.2..1.0).contains(&edge), "Expected about 0.4% but got {:?}%", edge);
+
+        // taking away doubling after splits and paying naturals 6:5 both cost the player
+        let worse_rules = RulesConfig { blackjack_payout: 1.2, ..Default::default() };
+        let worse_edge = house_edge(&worse_rules, 6, 400_000).unwrap();
+        assert!(worse_edge > edge + 1.0, "Expected well above {:?}% but got {:?}%", edge, worse_edge);
+    }
+
+    #[test]
+    fn test_check_outcomes_catches_corrupted_probabilities() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Eight, Card::Eight],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 500,
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+        assert_eq!(Ok(()), action_outcomes.check_outcomes());
+
+        action_outcomes.hit_twice.loss += 0.1;
+        assert!(matches!(
+            action_outcomes.check_outcomes(),
+            Err(InvalidOutcomesError::ProbabilitiesDontSumToOne("hit_twice", _))
+        ));
+
+        action_outcomes.hit_twice.loss -= 0.1;
+        action_outcomes.stand.estimated_value = f64::NAN;
+        assert_eq!(Err(InvalidOutcomesError::NotFinite("stand")), action_outcomes.check_outcomes());
+    }
+
+    #[test]
+    fn test_bet_size_sweep_scales_ev_linearly_and_variance_quadratically() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 1.0,
+            num_sims: 2_000,
+            ..Default::default()
+        };
+        let sweep = action_outcomes.generate_bet_size_sweep(&data, &[1.0, 2.0, 5.0]);
+
+        assert_eq!(3, sweep.points.len());
+        let unit = &sweep.points[0];
+        assert!(unit.variance > 0.0);
+        for point in &sweep.points {
+            assert!((point.ev - unit.ev * point.bet).abs() < 1e-9, "Got {:?}", point);
+            assert!((point.variance - unit.variance * point.bet * point.bet).abs() < 1e-9, "Got {:?}", point);
+        }
+    }
+
+    #[test]
+    fn test_split_variance_is_the_sample_variance_of_both_hands_together() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Eight, Card::Eight],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 5_000,
+            seed: Some(444),
+            ..Default::default()
+        };
+        let split = action_outcomes.generate_outcomes(&data, BlackJackAction::SPLIT(1));
+
+        // the same variance the standard error comes from
+        let std_error_per_bet = split.std_error.unwrap() / data.bet_size;
+        let variance_from_std_error = std_error_per_bet.powi(2) * split.num_sims as f64;
+        assert!((split.unit_variance() - variance_from_std_error).abs() < 1e-9,
+                "Got {:?} and {:?}", split.unit_variance(), variance_from_std_error);
+        // treating the averaged probabilities as one hand at twice the stake assumes both hands
+        // always finish alike, which overstates it
+        assert!(split.unit_variance() < split.single_hand_variance(),
+                "Got {:?} and {:?}", split.unit_variance(), split.single_hand_variance());
+    }
+
+    #[test]
+    fn test_split_ace_reaching_21_pays_even_money() {
+        let player_cards = vec![Card::Ace, Card::Ace];
+        let action = BlackJackAction::SPLIT(3);
+        let hands = handle_player_hands(
+            &player_cards,
+            &action,
+            &mut || Some(Card::King),
+            &|_| false,
+            &|_| false,
+            &RulesConfig::default()
+        );
+
+        let split_ace = (vec![Card::Ace, Card::King], 1.0);
+        assert_eq!(vec![split_ace.clone(), split_ace], hands, "split aces receive one card");
+        let results: Vec<HandResult> = hands
+            .iter()
+            .map(|(cards, stake)| {
+                let natural = is_player_natural(&player_cards, cards, &action);
+                assert!(!natural);
+                let total = best_hand_total(cards);
+                HandResult { outcome: compare_totals(total, Some(20)), total, stake: *stake, natural }
+            })
+            .collect();
+
+        let mut tally = OutcomeTally::new(&RulesConfig::default());
+        let net = tally.record(&results, Some(20), 1.0);
+        assert_eq!(2.0, net, "each 21 wins even money");
+        assert_eq!(0.0, tally.to_outcomes(&action, 10.0).blackjack);
+
+        assert!(is_player_natural(&[Card::Ace, Card::King], &[Card::Ace, Card::King], &BlackJackAction::STAND));
+    }
+
+    #[test]
+    fn test_split_aces_get_exactly_one_card_each() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ace, Card::Ace],
+            dealer_card: vec![Card::Nine],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 5_000,
+            seed: Some(511),
+            ..Default::default()
+        };
+
+        let mut deck = remaining_deck(&data);
+        let mut rng = SimRng::from_seed(data.seed);
+        for _ in 0..100 {
+            let hands = handle_player_hands(&data.current_cards, &BlackJackAction::SPLIT(3), &mut || deck.take_random_card_using(&mut rng), &|_| false, &|_| false, &RulesConfig::default());
+            assert_eq!(2, hands.len());
+            assert!(hands.iter().all(|(cards, _)| cards.len() == 2 && cards[0] == Card::Ace), "Got {:?}", hands);
+        }
+
+        let split = action_outcomes.generate_outcomes_with(&data, BlackJackAction::SPLIT(3), &mut |cards, _, _| {
+            assert_eq!(2, cards.len(), "Got {:?}", cards);
+        });
+        assert_eq!(0.0, split.blackjack, "a split ace and a ten is 21, not a natural");
+        assert!(split.win > 0.0);
+    }
+
+    #[test]
+    fn test_resplitting_aces_up_to_four_hands() {
+        let aces = [Card::Ace, Card::Ace];
+        let rules = RulesConfig { max_split_hands: 4, ..Default::default() };
+        let dealt = || [Card::Ace, Card::Ace, Card::King, Card::Ace, Card::Five, Card::Nine, Card::Seven].into_iter();
+
+        // the first hand is dealt two more aces, which make the third and fourth hands, so
+        // the ace dealt to the second hand has to stay with it
+        let mut cards = dealt();
+        let hands = handle_player_hands(&aces, &BlackJackAction::SPLIT(1), &mut || cards.next(), &|_| false, &|_| false, &rules);
+        let expected = vec![
+            (vec![Card::Ace, Card::King], 1.0),
+            (vec![Card::Ace, Card::Ace], 1.0),
+            (vec![Card::Ace, Card::Five], 1.0),
+            (vec![Card::Ace, Card::Nine], 1.0)
+        ];
+        assert_eq!(expected, hands);
+
+        let mut cards = dealt();
+        let hands = handle_player_hands(&aces, &BlackJackAction::SPLIT(1), &mut || cards.next(), &|_| false, &|_| false, &RulesConfig::default());
+        assert_eq!(vec![(vec![Card::Ace, Card::Ace], 1.0), (vec![Card::Ace, Card::Ace], 1.0)], hands);
+
+        let no_split = RulesConfig { max_split_hands: 1, ..Default::default() };
+        assert_eq!(Err(InvalidStateError::TooFewSplitHands(1)), no_split.validate());
+    }
+
+    #[test]
+    fn test_dealer_peeks_for_a_natural_before_the_player_acts() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ace],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(513),
+            ..Default::default()
+        };
+
+        // the player never gets to draw against a dealer natural, and only loses the one bet
+        let mut dealer_naturals = 0;
+        let double = action_outcomes.generate_outcomes_with(&data, BlackJackAction::DOUBLE, &mut |cards, dealer_total, net| {
+            if cards.len() == 2 {
+                dealer_naturals += 1;
+                assert_eq!((Some(21), -1.0), (dealer_total, net));
+            }
+        });
+        let natural_frequency = dealer_naturals as f64 / 20_000.0;
+        assert!((natural_frequency - 96.0 / 310.0).abs() < 0.015, "Got {:?}", natural_frequency);
+        assert!(double.loss > natural_frequency);
+
+        // a seven never has a natural, so the same hand loses far less often
+        let hit_against_ace = action_outcomes.generate_outcomes(&data, BlackJackAction::HIT(1));
+        let hit_against_seven = action_outcomes.generate_outcomes(&UserDataState { dealer_card: vec![Card::Seven], ..data.clone() }, BlackJackAction::HIT(1));
+        assert!(hit_against_ace.loss > hit_against_seven.loss + 0.1, "Got {:?} and {:?}", hit_against_ace.loss, hit_against_seven.loss);
+
+        // surrender comes after the peek, and a player natural pushes against the dealer's
+        let surrender = action_outcomes.generate_outcomes(&data, BlackJackAction::SURRENDER);
+        assert!(surrender.ev_per_bet < -0.6, "Got {:?}", surrender.ev_per_bet);
+        let natural = action_outcomes.generate_outcomes(&UserDataState { current_cards: vec![Card::Ace, Card::King], ..data }, BlackJackAction::STAND);
+        assert!(natural.tie > 95.0 / 310.0 - 0.015, "Got {:?}", natural.tie);
+        assert_eq!(natural.win, natural.blackjack);
+    }
+
+    #[test]
+    fn test_doubling_against_a_ten_is_worth_less_without_a_hole_card() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Six, Card::Five],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 40_000,
+            seed: Some(539),
+            ..Default::default()
+        };
+        let us_peek = action_outcomes.generate_outcomes(&data, BlackJackAction::DOUBLE);
+
+        let data = UserDataState { rules: RulesConfig { european_no_hole_card: true, ..Default::default() }, ..data };
+        let mut lost_a_double_to_a_natural = false;
+        let enhc = action_outcomes.generate_outcomes_with(&data, BlackJackAction::DOUBLE, &mut |cards, dealer_total, net| {
+            lost_a_double_to_a_natural |= dealer_total == Some(21) && best_hand_total(cards) == Some(21) && net == -2.0;
+        });
+        assert!(lost_a_double_to_a_natural, "a dealer natural beats even a doubled 21");
+
+        // the dealer has a natural about 1 time in 13 with a ten up, which now costs both bets
+        // rather than just the original one
+        let difference = us_peek.ev_per_bet - enhc.ev_per_bet;
+        assert!(difference > 0.04, "Got {:?} under US rules and {:?} under ENHC", us_peek.ev_per_bet, enhc.ev_per_bet);
+    }
+
+    #[test]
+    fn test_split_eights_against_six_plays_two_hands() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Eight, Card::Eight],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(510),
+            ..Default::default()
+        };
+
+        let mut hands = handle_player_hands(&data.current_cards, &BlackJackAction::SPLIT(1), &mut || Some(Card::Three), &|_| false, &|_| false, &RulesConfig::default());
+        assert_eq!(vec![(vec![Card::Eight, Card::Three], 1.0), (vec![Card::Eight, Card::Three], 1.0)], hands);
+        hands = handle_player_hands(&data.current_cards, &BlackJackAction::STAND, &mut || None, &|_| false, &|_| false, &RulesConfig::default());
+        assert_eq!(vec![(vec![Card::Eight, Card::Eight], 1.0)], hands);
+
+        let mut pushed_overall = false;
+        let split = action_outcomes.generate_outcomes_with(&data, BlackJackAction::SPLIT(1), &mut |cards, _, net| {
+            assert_eq!(2, cards.len());
+            assert!((-2.0..=2.0).contains(&net), "Got {:?}", net);
+            pushed_overall |= net == 0.0;
+        });
+        assert!(pushed_overall, "one hand winning and the other losing nets nothing");
+        assert_eq!(2.0, split.stake);
+        assert!((split.win + split.loss + split.tie - 1.0).abs() < 1e-9);
+
+        // splitting the 16 beats standing on it, and each hand contributes its own bet
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert!(split.ev_per_bet > stand.ev_per_bet + 0.1, "Got {:?} and {:?}", split.ev_per_bet, stand.ev_per_bet);
+        let per_hand_ev = split.win - split.loss;
+        assert!((split.ev_per_bet - 2.0 * per_hand_ev).abs() < 1e-9, "Got {:?} and {:?}", split.ev_per_bet, per_hand_ev);
+    }
+
+    #[test]
+    fn test_doubling_after_split_raises_the_split_ev() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Eight, Card::Eight],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 40_000,
+            seed: Some(528),
+            ..Default::default()
+        };
+        let without_das = action_outcomes.generate_outcomes(&data, BlackJackAction::SPLIT(1));
+
+        let data = UserDataState { rules: RulesConfig { double_after_split: true, ..Default::default() }, ..data };
+        let mut doubled = false;
+        let with_das = action_outcomes.generate_outcomes_with(&data, BlackJackAction::SPLIT(1), &mut |cards, _, net| {
+            // 8+2 and 8+3 are worth doubling against a six, and a doubled hand is played to 3 cards
+            doubled |= cards.len() == 3;
+            assert!((-4.0..=4.0).contains(&net), "Got {:?}", net);
+        });
+        assert!(doubled);
+        assert!(with_das.ev_per_bet > without_das.ev_per_bet + 0.02, "Got {:?} and {:?}", with_das.ev_per_bet, without_das.ev_per_bet);
+
+        // split aces still only get one card each
+        let data = UserDataState { current_cards: vec![Card::Ace, Card::Ace], ..data };
+        action_outcomes.generate_outcomes_with(&data, BlackJackAction::SPLIT(1), &mut |cards, _, _| assert_eq!(2, cards.len()));
+    }
+
+    #[test]
+    fn test_final_snapshot_matches_full_run() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_050,
+            ..Default::default()
+        };
+
+        let mut snapshots = Vec::new();
+        let outcomes = action_outcomes.generate_outcomes_with_snapshots(
+            &data, BlackJackAction::HIT(1), &mut |_, _, _| {}, 100, &mut |snapshot| {
+                snapshots.push(snapshot.clone());
+                ControlFlow::Continue(())
+            }
+        );
+
+        // one every 100 simulations plus the final partial block
+        assert_eq!(11, snapshots.len());
+        assert!(snapshots.iter().all(|snapshot| (snapshot.win + snapshot.loss + snapshot.tie - 1.0).abs() < 1e-9));
+        assert_eq!(&outcomes, snapshots.last().unwrap());
+    }
+
+    #[test]
+    fn test_outcomes_map_separates_blackjack_and_sums_to_one() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ace, Card::King],
+            dealer_card: vec![Card::Nine],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 2_000,
+            ..Default::default()
+        };
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        let map = stand.outcome_probabilities();
+
+        assert_eq!(stand.win, stand.blackjack, "every win with a natural is a blackjack");
+        assert_eq!(0.0, map["win"]);
+        let total: f64 = map.values().sum();
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+
+        let data = UserDataState { current_cards: vec![Card::Ten, Card::Queen], ..data };
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!(0.0, stand.blackjack);
+        let total: f64 = stand.outcome_probabilities().values().sum();
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+    }
+
+    #[test]
+    fn test_any_two_ten_value_cards_can_split() {
+        assert!(can_split_hand(&[Card::Ten, Card::Jack]));
+        assert!(can_split_hand(&[Card::King, Card::Queen]));
+        assert!(can_split_hand(&[Card::Eight, Card::Eight]));
+        assert!(!can_split_hand(&[Card::Nine, Card::Ten]));
+        assert!(!can_split_hand(&[Card::Ten, Card::Jack, Card::Two]));
+
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Jack],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000,
+            ..Default::default()
+        });
+        assert!(action_outcomes.split_hit_once.applicable);
+
+        // each split hand keeps a different one of the cards
+        let hands = handle_player_hands(&[Card::Ten, Card::Jack], &BlackJackAction::SPLIT(1), &mut || Some(Card::Nine), &|_| false, &|_| false, &RulesConfig::default());
+        assert_eq!(vec![Card::Ten, Card::Nine], hands[0].0);
+        assert_eq!(vec![Card::Jack, Card::Nine], hands[1].0);
+    }
+
+    #[test]
+    fn test_split_not_applicable_reason_for_non_pair() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        });
+
+        let pair_required = Some("split requires a pair".to_string());
+        assert_eq!(pair_required, action_outcomes.split_hit_once.reason);
+        assert_eq!(pair_required, action_outcomes.split_hit_twice.reason);
+        assert_eq!(pair_required, action_outcomes.split_hit_thrice.reason);
+        assert_eq!(None, action_outcomes.hit_once.reason);
+        assert_eq!(None, action_outcomes.stand.reason);
+    }
+
+    #[test]
+    fn test_recommend_best_action_lower_variance_breaks_near_tie() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.split_hit_once = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.split_hit_twice = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.split_hit_thrice = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.hit_twice = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.hit_thrice = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.hit_once_then_optimal = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.hit_then_double = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.double_down = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.surrender = ProbabilityValueOutcomes::not_applicable("test");
+        action_outcomes.stand = ProbabilityValueOutcomes {
+            estimated_value: -10.0,
+            win: 0.45,
+            loss: 0.55,
+            tie: 0.0,
+            net_variance: 0.99,
+            ..ProbabilityValueOutcomes::new()
+        };
+        action_outcomes.hit_once = ProbabilityValueOutcomes {
+            estimated_value: -10.0 - 1e-12,
+            win: 0.3,
+            loss: 0.4,
+            tie: 0.3,
+            net_variance: 0.69,
+            ..ProbabilityValueOutcomes::new()
+        };
+
+        assert_eq!("stand", action_outcomes.recommend_best_action(TieBreak::FixedOrder));
+        assert_eq!("hit_once", action_outcomes.recommend_best_action(TieBreak::LowerVariance));
+    }
+
+    #[test]
+    fn test_recommend_best_action_prefers_highest_ev() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.hit_twice.estimated_value = 25.0;
+
+        assert_eq!("hit_twice", action_outcomes.recommend_best_action(TieBreak::LowerVariance));
+    }
+
+    #[test]
+    fn test_progress_covers_every_action_in_percent_steps() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000,
+            ..Default::default()
+        };
+
+        let mut fractions = Vec::new();
+        let completed = action_outcomes.populate_action_outcomes_with_progress(&data, &mut |fraction| fractions.push(fraction), &mut || false);
+        assert!(completed);
+
+        // the splits aren't simulated for a non-pair, but still count towards the whole
+        let percents: Vec<_> = fractions.iter().map(|fraction| (fraction * 100.0 + 1e-9) as u32).collect();
+        assert!(percents.windows(2).all(|pair| pair[0] < pair[1]), "Got {:?}", fractions);
+        assert!(fractions.len() >= 50 && fractions.len() <= 100, "Got {:?} reports", fractions.len());
+        assert_eq!(Some(&1.0), fractions.last());
+        assert!(!action_outcomes.split_hit_once.applicable);
+    }
+
+    #[test]
+    fn test_cancelling_stops_the_simulations_early() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000_000,
+            ..Default::default()
+        };
+
+        // give up on the third check, part way through the first action
+        let mut checks = 0;
+        let mut last_fraction = 0.0;
+        let completed = action_outcomes.populate_action_outcomes_with_progress(
+            &data,
+            &mut |fraction| last_fraction = fraction,
+            &mut || { checks += 1; checks == 3 }
+        );
+
+        assert!(!completed);
+        assert_eq!(3, checks, "nothing is checked once cancelled");
+        assert!(last_fraction < 0.01, "Got {:?}", last_fraction);
+        assert_eq!(3 * CANCEL_CHECK_SIMS, action_outcomes.hit_once.num_sims + action_outcomes.hit_once.discarded_sims);
+        assert_eq!(Some("the simulation was cancelled".to_string()), action_outcomes.stand.reason);
+    }
+
+    #[test]
+    fn test_hard_20_best_action_is_stand() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 2_000,
+            ..Default::default()
+        });
+        assert_eq!("stand", action_outcomes.best_action());
+
+        // a non-pair's splits stay at their defaults, which must not win on a losing hand
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 2_000,
+            ..Default::default()
+        });
+        assert!(!action_outcomes.split_hit_once.applicable);
+        assert!(!action_outcomes.best_action().starts_with("split"), "Got {:?}", action_outcomes.best_action());
+
+        let mut tied = ActionOutcomes::new();
+        tied.hit_once.estimated_value = tied.stand.estimated_value;
+        assert_eq!("stand", tied.best_action());
+        assert_eq!(RecommendedAction::Stand, tied.best_action_kind());
+    }
+
+    #[test]
+    fn test_actions_and_outcomes_serialize_with_their_names() {
+        let split = serde_json::to_string(&BlackJackAction::SPLIT(2)).unwrap();
+        assert_eq!(r#"{"action":"split","value":2}"#, split);
+        assert_eq!(r#"{"action":"stand"}"#, serde_json::to_string(&BlackJackAction::STAND).unwrap());
+        assert_eq!(r#"{"action":"hit_then_optimal","value":1}"#, serde_json::to_string(&BlackJackAction::HIT_THEN_OPTIMAL(1)).unwrap());
+        assert_eq!(BlackJackAction::SPLIT(2), serde_json::from_str(&split).unwrap());
+        assert_eq!(r#""loss""#, serde_json::to_string(&GameOutcome::LOSS).unwrap());
+
+        // every recommendable label has an enum value and a move
+        for (label, _, _) in ActionOutcomes::new().labelled_actions() {
+            assert!(RecommendedAction::from_label(label).is_some(), "Got {:?}", label);
+            assert!(action_for_label(label).is_some(), "Got {:?}", label);
+        }
+    }
+
+    #[test]
+    fn test_msgpack_round_trips_action_outcomes() {
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.populate_action_outcomes(&UserDataState {
+            current_cards: vec![Card::Eight, Card::Eight],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        });
+
+        let bytes = action_outcomes.to_msgpack().expect("Test failed: Should serialize");
+        let decoded: ActionOutcomes = rmp_serde::from_slice(&bytes).expect("Test failed: Should deserialize");
+
+        assert_eq!(action_outcomes, decoded);
+    }
+
+    #[test]
+    fn test_msgpack_batch_keeps_each_entry_or_its_error() {
+        let holder = |bet_size: &str| UserDataStateHolder::new(
+            vec![Card::Ten, Card::Six],
+            vec![Card::Ten],
+            "6".to_string(),
+            bet_size.to_string(),
+            "200".to_string()
+        );
+        let entries = ActionOutcomes::new().simulate_batch(vec![holder("10"), holder("ten")]);
+
+        let bytes = batch_to_msgpack(&entries).expect("Test failed: Should serialize");
+        let decoded: serde_json::Value = rmp_serde::from_slice(&bytes).expect("Test failed: Should deserialize");
+
+        let (outcomes, metadata) = entries[0].as_ref().unwrap();
+        assert_eq!(serde_json::to_value(&outcomes.stand).unwrap(), decoded[0]["stand"]);
+        assert_eq!(serde_json::Value::from(metadata.num_sims), decoded[0]["metadata"]["num_sims"]);
+        assert_eq!("ParseBet", decoded[1]["error"]["kind"]);
+        assert!(decoded[1]["error"]["message"].as_str().is_some_and(|message| message.contains("bet_size")));
+    }
+
+    #[test]
+    fn test_response_keeps_the_outcomes_next_to_the_metadata() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 1,
+            bet_size: 10.0,
+            num_sims: 500,
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+
+        let response = ActionOutcomesResponse { outcomes: &action_outcomes, metadata: simulation_metadata(&data, &action_outcomes, performance_now()) };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(serde_json::to_value(&action_outcomes.stand).unwrap(), json["stand"]);
+        let metadata = SimulationMetadata { num_sims: 500, elapsed_ms: None, deck_size: 49 };
+        assert_eq!(serde_json::to_value(&metadata).unwrap(), json["metadata"]);
+    }
+
+    #[test]
+    fn test_metadata_reports_the_sims_run_when_every_action_stops_early() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 50_000,
+            target_ci_width: Some(1_000.0),
+            seed: Some(555),
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+
+        let metadata = simulation_metadata(&data, &action_outcomes, None);
+        assert_eq!(CONVERGENCE_CHECK_SIMS, metadata.num_sims);
+        assert_eq!(action_outcomes.hit_once.num_sims, metadata.num_sims);
+    }
+
+    #[test]
+    fn test_outcomes_are_finite_and_in_range_across_inputs() {
+        let hands = [
+            vec![Card::Ace, Card::King],
+            vec![Card::Ten, Card::Six],
+            vec![Card::Eight, Card::Eight],
+            vec![Card::Two, Card::Three, Card::Four],
+        ];
+        let dealer_cards = [Card::Ace, Card::Six, Card::Ten];
+
+        for hand in hands.iter() {
+            for dealer_card in dealer_cards.iter() {
+                for num_sims in [1, 10, 500] {
+                    let mut action_outcomes = ActionOutcomes::new();
+                    action_outcomes.populate_action_outcomes(&UserDataState {
+                        current_cards: hand.clone(),
+                        dealer_card: vec![dealer_card.clone()],
+                        num_decks: 1,
+                        bet_size: 10.0,
+                        num_sims,
+                        ..Default::default()
+                    });
+
+                    for outcomes in action_outcomes.all_outcomes_mut() {
+                        assert!(outcomes.estimated_value.is_finite());
+                        assert!(outcomes.average_cards_drawn.is_finite());
+                        for probability in [outcomes.win, outcomes.loss, outcomes.tie] {
+                            assert!((0.0..=1.0).contains(&probability),
+                                    "Expected probability in [0, 1] but got {:?}", probability);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_sims_do_not_produce_nan() {
+        let action_outcomes = ActionOutcomes::new();
+        let outcome = action_outcomes.generate_outcomes(
+            &UserDataState {
+                current_cards: vec![Card::Ten, Card::Six],
+                dealer_card: vec![Card::Ten],
+                num_decks: 1,
+                bet_size: 10.0,
+                num_sims: 0,
+                ..Default::default()
+            },
+            BlackJackAction::HIT(1)
+        );
+
+        assert_eq!(0.0, outcome.estimated_value);
+        assert_eq!(0.0, outcome.win);
+        assert_eq!(0.0, outcome.average_cards_drawn);
+    }
+
+    #[test]
+    fn test_sanitise_replaces_non_finite_values() {
+        let mut outcomes = ProbabilityValueOutcomes {
+            estimated_value: f64::INFINITY,
+            win: f64::NAN,
+            loss: 1.5,
+            tie: -0.1,
+            ..ProbabilityValueOutcomes::new()
+        };
+        outcomes.sanitise();
+
+        assert_eq!(0.0, outcomes.estimated_value);
+        assert_eq!(0.0, outcomes.win);
+        assert_eq!(1.0, outcomes.loss);
+        assert_eq!(0.0, outcomes.tie);
+    }
+
+    #[test]
+    fn test_deck_composition_counts_each_ten_value_card_separately() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::King],
+            dealer_card: vec![Card::Ten],
+            num_decks: 1,
+            bet_size: 10.0,
+            num_sims: 1,
+            ..Default::default()
+        };
+        let composition = deck_composition(&data);
+
+        assert_eq!(13, composition.len());
+        let count = |card: &str| composition.iter().find(|count| count.card == card).map(|count| count.count);
+        assert_eq!((Some(2), Some(4), Some(4), Some(3)), (count("Ten"), count("Jack"), count("Queen"), count("King")));
+        assert_eq!(Some(4), count("Ace"));
+        assert_eq!(49, composition.iter().map(|count| count.count).sum::<u32>());
+    }
+
+    #[test]
+    fn test_card_removal_effects_five_and_ace_have_opposite_signs() {
+        let effects = generate_card_removal_effects(&UserDataState {
+            current_cards: vec![Card::Six, Card::Four],
+            dealer_card: vec![Card::Six],
+            num_decks: 1,
+            bet_size: 1.0,
+            num_sims: 1,
+            ..Default::default()
+        });
+
+        let effect_of = |name: &str| effects
+            .iter()
+            .find(|effect| effect.card == name)
+            .map(|effect| effect.ev_change)
+            .unwrap();
+
+        assert_eq!(10, effects.len());
+        assert!(effect_of("Five") > 0.0, "Expected removing a five to help but got {:?}", effect_of("Five"));
+        assert!(effect_of("Ace") < 0.0, "Expected removing an ace to hurt but got {:?}", effect_of("Ace"));
+    }
+
+    #[test]
+    fn test_exact_dealer_totals_sum_to_one() {
+        let mut counts = Deck::new(&1).rank_counts();
+        counts[5] -= 1; // the dealer's six
+
+        let mut dealer_totals = [0.0; 22];
+        exact_dealer_totals(&[Card::Six], &mut counts, 1.0, &mut dealer_totals, &RulesConfig::default());
+
+        let total: f64 = dealer_totals.iter().sum();
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+    }
+
+    #[test]
+    fn test_dealer_outcome_chart_sums_to_one() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Ace],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(557),
+            ..Default::default()
+        };
+        let chart = simulate_dealer_outcomes(&data);
+        let total = chart.seventeen + chart.eighteen + chart.nineteen + chart.twenty + chart.twenty_one
+            + chart.blackjack + chart.bust + chart.under_seventeen;
+        assert!((total - 1.0).abs() < 1e-9, "Expected 1.0 but got {:?}", total);
+        assert_eq!(0.0, chart.under_seventeen);
 
-            let mut dealer_cards = data.dealer_card.clone();
-            handle_dealer_action(
-                &mut dealer_cards,
-                draw_card
-            );
+        // an ace has a ten underneath about 94 times in 309, and other 21s are much rarer
+        assert!((chart.blackjack - 94.0 / 309.0).abs() < 0.02, "Got {:?}", chart.blackjack);
+        assert!(chart.twenty_one < chart.blackjack, "Got {:?}", chart);
 
-            let outcome = evaluate_hands(
-                &player_cards,
-                &dealer_cards
-            );
+        // a six can never make a natural
+        let six = simulate_dealer_outcomes(&UserDataState { dealer_card: vec![Card::Six], ..data });
+        assert_eq!(0.0, six.blackjack);
+        assert!(six.twenty_one > 0.0);
+    }
 
-            match outcome {
-                GameOutcome::WIN => wins += 1,
-                GameOutcome::LOSS => losses += 1,
-                GameOutcome::TIE => ties += 1
-            }
-        }
+    #[test]
+    fn test_memoised_dealer_totals_match_a_simulated_dealer() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Two],
+            dealer_card: vec![Card::Five],
+            num_decks: 2,
+            num_sims: 100_000,
+            seed: Some(546),
+            ..Default::default()
+        };
+        let mut counts = remaining_deck(&data).rank_counts();
 
-        let win_probability = wins as f64 / data.num_sims as f64;
-        let loss_probability = losses as f64 / data.num_sims as f64;
-        let tie_probability = ties as f64 / data.num_sims as f64;
-        let estimated_value = (win_probability * data.bet_size)
-            - (loss_probability * data.bet_size); // ignore ties as it doesnt change ev
+        let mut memo = DealerTotalsMemo::new(&data.rules);
+        let mut dealer_totals = [0.0; 22];
+        memo.add_totals(&data.dealer_card, &mut counts, 1.0, &mut dealer_totals);
+        let simulated_bust = simulate_dealer_bust(&data);
+        let tolerance = 4.0 * binomial_std_error(simulated_bust, data.num_sims as f64);
+        assert!((dealer_totals[0] - simulated_bust).abs() < tolerance,
+                "Expected a bust chance within {:?} of {:?} but got {:?}", tolerance, simulated_bust, dealer_totals[0]);
 
-        ProbabilityValueOutcomes {
-            estimated_value,
-            win: win_probability,
-            loss: loss_probability,
-            tie: tie_probability
-        }
+        // a second lookup reuses every distribution instead of adding more
+        let memoised = memo.distributions.len();
+        let mut again = [0.0; 22];
+        memo.add_totals(&data.dealer_card, &mut counts, 1.0, &mut again);
+        assert_eq!(memoised, memo.distributions.len());
+        assert_eq!(dealer_totals, again);
     }
-}
 
-/// Makes a move depending on the given player action
-fn handle_player_action(
-    player_cards: &mut Vec<Card>,
-    action: &BlackJackAction,
-    draw_card: &mut impl FnMut()->Card
-) {
-    match action {
-        BlackJackAction::HIT(num_hits) => {
-            for _ in 0..*num_hits {
-                player_cards.push(draw_card());
-            }
+    /// The dealer's final totals from playing out every sequence of draws one card at a time,
+    /// the way the memo's distributions were worked out before it
+    fn naive_dealer_totals(
+        dealer_cards: &mut Vec<Card>,
+        counts: &mut [u32; 10],
+        probability: f64,
+        dealer_totals: &mut [f64; 22],
+        rules: &RulesConfig
+    ) {
+        let remaining: u32 = counts.iter().sum();
+        if !dealer_should_draw_under(dealer_cards, rules) || remaining == 0 {
+            dealer_totals[best_hand_total(dealer_cards).unwrap_or(0) as usize] += probability;
+            return;
         }
-        BlackJackAction::STAND => {
-            // do nothing if we stand
-        }
-        BlackJackAction::SPLIT(num_hits) => {
-            player_cards.remove(1);
+        for (index, card) in RANKS.iter().enumerate() {
+            if counts[index] == 0 { continue; }
 
-            // for the sake of simplicity, we just pretend the outcome of 1
-            // split hand is the outcome of both, since this is basically
-            // what happens given the law of large numbers
-            for _ in 0..*num_hits {
-                player_cards.push(draw_card());
-            }
+            let draw_probability = probability * counts[index] as f64 / remaining as f64;
+            counts[index] -= 1;
+            dealer_cards.push(card.clone());
+            naive_dealer_totals(dealer_cards, counts, draw_probability, dealer_totals, rules);
+            dealer_cards.pop();
+            counts[index] += 1;
         }
     }
-}
 
-/// Handles the dealer drawing until they reach 17 or higher
-fn handle_dealer_action(
-    dealer_cards: &mut Vec<Card>,
-    draw_card: &mut impl FnMut()->Card
-) {
-    // if any iteration of the dealer's hand is >= 17, then they stand
-    while evaluate_hand(&dealer_cards).iter().all(|x| *x <= 16) {
-        dealer_cards.push(draw_card());
+    /// The usual rules, hitting soft 17, and standing on 18 and on 16
+    fn dealer_rule_variants() -> [RulesConfig; 4] {
+        [
+            RulesConfig::default(),
+            RulesConfig { dealer_hits_soft_17: true, ..Default::default() },
+            RulesConfig { dealer_stand_value: 18, ..Default::default() },
+            RulesConfig { dealer_stand_value: 16, dealer_hits_soft_17: true, ..Default::default() }
+        ]
     }
-}
 
-/// Evaluates the players and dealers cards after they have both made their actions
-/// and then returns an outcome from the player's perspective.
-///
-/// They should win if their best hand beats the dealer's best hand
-/// Tie if their best hand matches the dealer's best hand
-/// Lose if their best hand is worse than the dealer's best hand
-fn evaluate_hands(players_cards: &Vec<Card>, dealers_cards: &Vec<Card>) -> GameOutcome {
-    let player_evaluations = evaluate_hand(players_cards);
-    let dealer_evaluations = evaluate_hand(dealers_cards);
-
-    let player_best_option = player_evaluations
-        .into_iter()
-        .filter(|&value| value <= 21)
-        .max();
+    #[test]
+    fn test_memoised_dealer_totals_match_playing_out_every_draw_under_each_rule() {
+        for rules in dealer_rule_variants() {
+            for upcard in [Card::Two, Card::Six, Card::Ten, Card::Ace] {
+                let mut counts = Deck::new(&1).rank_counts();
+                counts[upcard.rank_index().unwrap()] -= 1;
 
-    let dealer_best_option = dealer_evaluations
-        .into_iter()
-        .filter(|&value| value <= 21)
-        .max();
+                let mut memoised = [0.0; 22];
+                exact_dealer_totals(std::slice::from_ref(&upcard), &mut counts, 1.0, &mut memoised, &rules);
+                let mut naive = [0.0; 22];
+                naive_dealer_totals(&mut vec![upcard.clone()], &mut counts, 1.0, &mut naive, &rules);
 
-    match (player_best_option, dealer_best_option) {
-        (None, _) => GameOutcome::LOSS,
-        (Some(_), None) => GameOutcome::WIN,
-        (Some(player_best_value), Some(dealer_best_value)) => {
-            if player_best_value == dealer_best_value {
-                GameOutcome::TIE
-            } else if player_best_value > dealer_best_value {
-                GameOutcome::WIN
-            } else {
-                GameOutcome::LOSS
+                for (total, (memoised, naive)) in memoised.iter().zip(naive).enumerate() {
+                    assert!((memoised - naive).abs() < 1e-12,
+                            "Expected {:?} for {:?} against {:?} under {:?} but got {:?}", naive, total, upcard, rules, memoised);
+                }
             }
         }
     }
-}
-
-/// Check if the player's hand can be split, if it can, return true
-fn can_split_hand(hand: &Vec<Card>) -> bool {
-    hand.len() == 2 && hand[0] == hand[1]
-}
-
-
-/// Evaluates a hand and returns a list of possible values
-fn evaluate_hand(cards: &[Card]) -> Vec<u8> {
-    let value_mapping = cards
-        .iter()
-        .map(|card| card.get_card_values())
-        .collect();
-    generate_value_combinations(&value_mapping)
-}
-
-/// Generates all combinations of evaluations of a hand
-fn generate_value_combinations(card_values: &Vec<Vec<u8>>) -> Vec<u8> {
-    let n =  card_values.len();
-    let mut results = vec![];
-    let mut stack = vec![(0, 0)];
 
-    while let Some((index, current_sum)) = stack.pop() {
-        if index == n { // assure that we stop once we have combinations of length n
-            results.push(current_sum);
-            continue;
-        }
+    #[test]
+    fn test_memoised_evs_match_simulated_evs_under_each_dealer_rule() {
+        let action_outcomes = ActionOutcomes::new();
+        for rules in dealer_rule_variants() {
+            let data = UserDataState {
+                current_cards: vec![Card::Ten, Card::Seven],
+                dealer_card: vec![Card::Six],
+                num_decks: 1,
+                bet_size: 1.0,
+                num_sims: 40_000,
+                seed: Some(546),
+                rules,
+                ..Default::default()
+            };
+            for action in [|| BlackJackAction::STAND, || BlackJackAction::HIT(1)] {
+                let memoised = exact_shallow_outcomes(&data, &action()).expect("Test failed: Should be exact");
+                let simulated = action_outcomes.generate_outcomes(&data, action());
 
-        for &value in &card_values[index] {
-            stack.push((index + 1, current_sum + value));
+                let tolerance = 4.0 * simulated.std_error.unwrap();
+                assert!((memoised.ev_per_bet - simulated.ev_per_bet).abs() < tolerance,
+                        "Expected EVs within {:?} under {:?} but got {:?} memoised and {:?} simulated",
+                        tolerance, rules, memoised.ev_per_bet, simulated.ev_per_bet);
+            }
         }
     }
 
-    results
-}
+    /// Timing of the dealer's totals against every upcard from six decks, memoised against
+    /// played out draw by draw. Ignored as wall-clock times aren't reliable under load or in
+    /// debug builds. Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn test_memoised_dealer_totals_are_faster_than_playing_out_every_draw() {
+        let rules = RulesConfig::default();
+        let time = |memoised: bool| {
+            let start = std::time::Instant::now();
+            for upcard in &RANKS {
+                let mut counts = Deck::new(&6).rank_counts();
+                counts[upcard.rank_index().unwrap()] -= 1;
+                let dealer_totals = &mut [0.0; 22];
+                match memoised {
+                    true => exact_dealer_totals(std::slice::from_ref(upcard), &mut counts, 1.0, dealer_totals, &rules),
+                    false => naive_dealer_totals(&mut vec![upcard.clone()], &mut counts, 1.0, dealer_totals, &rules)
+                }
+            }
+            start.elapsed()
+        };
+        let naive_time = time(false);
+        let memoised_time = time(true);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        println!("every upcard from six decks: {:?} memoised vs {:?} played out", memoised_time, naive_time);
+        assert!(memoised_time < naive_time);
+    }
 
     #[test]
-    fn test_generate_value_combinations() {
-        let first_values = vec![1, 11];
-        let second_values = vec![7];
-        let third_values = vec![1, 11];
-        let input = vec![first_values, second_values, third_values];
-        let expected = vec![9, 19, 19, 29];
+    fn test_count_bias_keeps_whole_cards_and_exact_total() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Five],
+            num_decks: 6,
+            ..Default::default()
+        };
+        let unbiased = remaining_deck(&data);
 
-        let mut result = generate_value_combinations(&input);
-        result.sort();
+        for true_count in [-7.5, -2.0, 0.3, 1.0, 3.7, 12.0] {
+            let mut deck = remaining_deck(&data);
+            deck.apply_count_bias(true_count);
 
-        assert_eq!(expected, result, "Expected {:?} but got {:?}", expected, result);
+            // cards are whole by construction, so the total is what rounding could break
+            assert_eq!(4 * 6 * 13 - 3, deck.cards.len());
+        }
+
+        let mut biased = remaining_deck(&data);
+        biased.apply_count_bias(4.0);
+        let tens = |deck: &Deck| deck.rank_counts()[9];
+        let fives = |deck: &Deck| deck.rank_counts()[4];
+        assert!(tens(&biased) > tens(&unbiased));
+        assert!(fives(&biased) < fives(&unbiased));
     }
 
     #[test]
-    fn test_evaluate_hand() {
-        let hand = vec![Card::Ace, Card::Five, Card::Three];
-        let expected = vec![9, 19];
+    fn test_count_bias_is_deterministic() {
+        let mut first = Deck::new(&2);
+        let mut second = Deck::new(&2);
+        first.apply_count_bias(2.5);
+        second.apply_count_bias(2.5);
 
-        let mut result = evaluate_hand(&hand);
-        result.sort();
-
-        assert_eq!(expected, result, "Expected {:?} but got {:?}", expected, result);
+        assert_eq!(first.cards, second.cards);
     }
 
     #[test]
-    fn test_evaluate_hands_player_busts() {
-        let player_hand = vec![Card::Jack, Card::Five, Card::Seven];
-        let dealers_hand = vec![Card::Jack, Card::Six, Card::Queen];
-        let expected_outcome = GameOutcome::LOSS;
-
-        let actual_outcome = evaluate_hands(
-            &player_hand,
-            &dealers_hand
-        );
+    fn test_true_count_divides_by_remaining_decks() {
+        let data = UserDataState {
+            num_decks: 2,
+            bet_size: 100.0,
+            num_sims: 1,
+            ..Default::default()
+        };
+        assert_eq!(3.0, estimate_true_count(6, &data));
+        assert_eq!(-1.0, estimate_true_count(-6, &UserDataState { num_decks: 6, ..data.clone() }));
 
-        assert_eq!(expected_outcome, actual_outcome,
-                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+        // the known cards come out of a custom shoe too, here leaving three decks
+        let custom_shoe = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            custom_deck: Deck::new(&4).cards.split_off(49),
+            ..data
+        };
+        assert_eq!(3.0, decks_remaining(&custom_shoe));
+        assert_eq!(2.0, estimate_true_count(6, &custom_shoe));
+        let summary = summarise_count(&custom_shoe);
+        assert_eq!(estimate_true_count(summary.running_count, &custom_shoe), summary.true_count);
     }
 
     #[test]
-    fn test_evaluate_hands_player_dealer_busts_player_does_not_bust() {
-        let player_hand = vec![Card::Jack, Card::Five];
-        let dealers_hand = vec![Card::Jack, Card::Six, Card::Queen];
-        let expected_outcome = GameOutcome::WIN;
-
-        let actual_outcome = evaluate_hands(
-            &player_hand,
-            &dealers_hand
-        );
+    fn test_count_summary_tags_every_dealt_card() {
+        // the 6 counts +1 and the tens -1 each, then the seen cards add +4 (2-6) and -1 (ace)
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::King],
+            seen_cards: vec![Card::Two, Card::Three, Card::Four, Card::Five, Card::Seven, Card::Ace],
+            num_decks: 2,
+            bet_size: 100.0,
+            num_sims: 1,
+            ..Default::default()
+        };
+        let summary = summarise_count(&data);
+        assert_eq!(2, summary.running_count);
+        assert_eq!((104.0 - 9.0) / 52.0, summary.decks_remaining);
+        assert!((summary.true_count - 2.0 * 52.0 / 95.0).abs() < 1e-12);
 
-        assert_eq!(expected_outcome, actual_outcome,
-                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+        // one card left, a two, divides by half a deck rather than 1/52 of one
+        let mut unseen = remaining_deck(&UserDataState { num_decks: 1, seen_cards: vec![], ..data.clone() });
+        unseen.remove_card_from_deck(&Card::Two);
+        let nearly_empty = UserDataState { num_decks: 1, seen_cards: unseen.cards, ..data };
+        let summary = summarise_count(&nearly_empty);
+        assert_eq!(1.0 / 52.0, summary.decks_remaining);
+        assert_eq!((-1, -2.0), (summary.running_count, summary.true_count));
     }
 
     #[test]
-    fn test_evaluate_hands_when_equal() {
-        let player_hand = vec![Card::Six, Card::Five];
-        let dealers_hand = vec![Card::Six, Card::Five];
-        let expected_outcome = GameOutcome::TIE;
-
-        let actual_outcome = evaluate_hands(
-            &player_hand,
-            &dealers_hand
-        );
+    fn test_break_even_win_probability() {
+        assert_eq!(0.5, break_even_win_probability(1.0));
+        assert!((break_even_win_probability(BLACKJACK_PAYOUT) - 0.4).abs() < 1e-12);
+    }
 
-        assert_eq!(expected_outcome, actual_outcome,
-                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+    #[test]
+    fn test_textbook_action_stands_on_hard_seventeen() {
+        for dealer_upcard in 2..=11 {
+            assert_eq!("stand", textbook_action(17, false, false, dealer_upcard));
+        }
     }
 
     #[test]
-    fn test_evaluate_hands_when_equal_with_aces() {
-        let player_hand = vec![Card::Six, Card::Five, Card::Ace];
-        let dealers_hand = vec![Card::Six, Card::Five, Card::Ace];
-        let expected_outcome = GameOutcome::TIE;
+    fn test_textbook_action_well_known_cells() {
+        assert_eq!("hit", textbook_action(16, false, false, 10));
+        assert_eq!("double", textbook_action(11, false, false, 6));
+        assert_eq!("split", textbook_action(16, false, true, 10)); // eights
+        assert_eq!("stand", textbook_action(20, false, true, 6)); // tens
+        assert_eq!("split", textbook_action(12, true, true, 1)); // aces
+        assert_eq!("double", textbook_action(18, true, false, 4));
+        assert_eq!("hit", textbook_action(18, true, false, 9));
+        assert_eq!("unknown", textbook_action(22, false, false, 6));
+    }
 
-        let actual_outcome = evaluate_hands(
-            &player_hand,
-            &dealers_hand
-        );
+    #[test]
+    fn test_simulation_agrees_with_basic_strategy_on_eleven_against_six() {
+        let mut action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Six, Card::Five],
+            dealer_card: vec![Card::Six],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 5_000,
+            seed: Some(531),
+            ..Default::default()
+        };
+        action_outcomes.populate_action_outcomes(&data);
+        let comparison = action_outcomes.strategy_comparison(&data);
+        let expected = StrategyComparison {
+            simulated_action: "double_down".to_string(),
+            basic_strategy_action: "double".to_string(),
+            agrees: true
+        };
+        assert_eq!(expected, comparison);
 
-        assert_eq!(expected_outcome, actual_outcome,
-                   "Expected {:?} but got {:?}", expected_outcome, actual_outcome);
+        let sixteen = UserDataState { current_cards: vec![Card::Ten, Card::Six], dealer_card: vec![Card::Ten], ..data };
+        assert_eq!("hit", basic_strategy_action(&sixteen));
+        let aces = UserDataState { current_cards: vec![Card::Ace, Card::Ace], dealer_card: vec![Card::Ace], ..sixteen };
+        assert_eq!("split", basic_strategy_action(&aces));
     }
 
     #[test]
-    fn test_generate_hit_outcomes_ten_thousand_sims() {
-        let action_outcomes = ActionOutcomes::new();
-        let outcome = action_outcomes.generate_outcomes(
-            &UserDataState {
-                current_cards: vec![Card::Ace, Card::Jack],
-                dealer_card: vec![Card::Six],
-                num_decks: 10,
-                bet_size: 100.0,
-                num_sims: 10_000,
-            },
-            BlackJackAction::HIT(1)
-        );
+    fn test_parse_cards_from_shorthand() {
+        assert_eq!(Ok(vec![Card::Ace, Card::Ten, Card::King]), parse_card_tokens("A 10 K"));
+        assert_eq!(Ok(vec![Card::Ace, Card::Ten, Card::Queen]), parse_card_tokens("AS,TH,QD"));
+        assert_eq!(Ok(vec![Card::Ten, Card::Jack, Card::Two]), parse_card_tokens("  10c , j\t 2h  "));
+        assert_eq!(Ok(vec![]), parse_card_tokens(" , "));
+        assert_eq!(Err("\"X\" is not a card".to_string()), parse_card_tokens("A X"));
+        assert!(parse_card_tokens("S").is_err() && parse_card_tokens("11").is_err());
 
-        assert_ne!(outcome.win, 1.0);
-        assert_ne!(outcome.win, 0.0);
+        for card in DECK_CARDS {
+            assert_eq!(Ok(vec![card.clone()]), parse_card_tokens(&card_to_string(card)));
+        }
+    }
 
-        assert_ne!(outcome.loss, 1.0);
-        assert_ne!(outcome.loss, 0.0);
+    #[test]
+    fn test_validate_rejects_too_many_decks() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Six],
+            dealer_card: vec![Card::Ten],
+            num_decks: 200,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        };
+        assert_eq!(Err(InvalidStateError::TooManyDecks(200)), data.validate());
 
-        assert_ne!(outcome.tie, 1.0);
-        assert_ne!(outcome.tie, 0.0);
+        let data = UserDataState { num_decks: 8, ..data };
+        assert_eq!(Ok(()), data.validate());
     }
 
     #[test]
-    fn test_generate_stand_outcomes_ten_thousand_sims() {
+    fn test_seen_low_cards_raise_the_stand_ev() {
         let action_outcomes = ActionOutcomes::new();
-        let outcome = action_outcomes.generate_outcomes(
-            &UserDataState {
-                current_cards: vec![Card::Ace, Card::Queen],
-                dealer_card: vec![Card::Six],
-                num_decks: 10,
-                bet_size: 100.0,
-                num_sims: 10_000,
-            },
-            BlackJackAction::STAND
-        );
-
-        assert!(outcome.win > outcome.loss);
-
-        assert_ne!(outcome.win, 1.0);
-        assert_ne!(outcome.win, 0.0);
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Eight],
+            dealer_card: vec![Card::Six],
+            num_decks: 1,
+            bet_size: 10.0,
+            num_sims: 20_000,
+            seed: Some(524),
+            ..Default::default()
+        };
+        // nearly every 2 to 6 is gone, leaving a shoe rich in tens for the dealer to bust on
+        let seen_cards: Vec<Card> = [Card::Two, Card::Three, Card::Four, Card::Five, Card::Six]
+            .iter()
+            .flat_map(|card| vec![card.clone(); 3])
+            .collect();
+        let counted = UserDataState { seen_cards, ..data.clone() };
 
-        assert_eq!(outcome.loss, 0.0);
+        assert_eq!(Ok(()), counted.validate());
+        assert_eq!(52 - 3 - 15, remaining_deck(&counted).cards.len());
+        let fresh_stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        let counted_stand = action_outcomes.generate_outcomes(&counted, BlackJackAction::STAND);
+        assert!(counted_stand.ev_per_bet > fresh_stand.ev_per_bet + 0.05,
+                "Got {:?} and {:?}", counted_stand.ev_per_bet, fresh_stand.ev_per_bet);
 
-        assert_ne!(outcome.tie, 1.0);
-        assert_ne!(outcome.tie, 0.0);
+        // seen cards count towards what the shoe holds, and something has to be left to draw
+        let too_many_sixes = UserDataState { seen_cards: vec![Card::Six; 4], ..data.clone() };
+        assert_eq!(Err(InvalidStateError::MoreCardsThanTheShoe(Card::Six, 5)), too_many_sixes.validate());
+        let whole_shoe: Vec<Card> = DECK_CARDS
+            .iter()
+            .flat_map(|card| vec![card.clone(); 4])
+            .filter(|card| ![Card::Ten, Card::Eight, Card::Six].contains(card))
+            .chain([Card::Ten, Card::Eight, Card::Six].iter().flat_map(|card| vec![card.clone(); 3]))
+            .collect();
+        assert_eq!(Err(InvalidStateError::NoCardsLeft), UserDataState { seen_cards: whole_shoe, ..data }.validate());
     }
 
     #[test]
-    fn test_generate_stand_outcomes_expected_loss_ten_thousand_sims() {
-        let action_outcomes = ActionOutcomes::new();
-        let outcome = action_outcomes.generate_outcomes(
-            &UserDataState {
-                current_cards: vec![Card::Two],
-                dealer_card: vec![Card::Six],
-                num_decks: 10,
-                bet_size: 100.0,
-                num_sims: 10_000,
-            },
-            BlackJackAction::STAND
-        );
+    fn test_validate_rejects_more_cards_than_the_shoe_holds() {
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Ten, Card::Ten, Card::Ten],
+            dealer_card: vec![Card::Ten],
+            num_decks: 1,
+            bet_size: 100.0,
+            num_sims: 1_000,
+            ..Default::default()
+        };
+        assert_eq!(Err(InvalidStateError::MoreCardsThanTheShoe(Card::Ten, 5)), data.validate());
+        assert_eq!("5 of Ten were entered, more than the shoe holds", data.validate().unwrap_err().to_string());
 
-        assert!(outcome.loss > outcome.win);
+        // kings are a different card to tens, and a second deck has room for them all
+        assert_eq!(Ok(()), UserDataState { dealer_card: vec![Card::King], ..data.clone() }.validate());
+        assert_eq!(Ok(()), UserDataState { num_decks: 2, ..data.clone() }.validate());
+        let infinite_deck = RulesConfig { infinite_deck: true, ..Default::default() };
+        let data = UserDataState { rules: infinite_deck, ..data };
+        assert_eq!(Ok(()), data.validate());
+        ActionOutcomes::new().generate_outcomes(&data, BlackJackAction::STAND);
     }
 
     #[test]
@@ -639,6 +7213,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::single_match)]
     fn test_holder_to_user_data_conversion_err() {
         let user_data = UserDataStateHolder::new(
             vec![Card::Ace, Card::Two],
@@ -655,6 +7230,180 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_simulate_natively() {
+        let holder = |num_decks: &str| UserDataStateHolder::new(
+            vec![Card::Ten, Card::Queen],
+            vec![Card::Six],
+            num_decks.to_string(),
+            "10".to_string(),
+            "500".to_string()
+        );
+
+        // seeded, since 500 simulations of splitting the tens could otherwise beat standing
+        let mut seeded = holder("6");
+        seeded.set_seed(Some(526));
+        let action_outcomes = ActionOutcomes::simulate(seeded).expect("Test failed: Should simulate");
+        assert_eq!("stand", action_outcomes.best_action());
+        assert_eq!(Err("at least one deck is needed".to_string()), ActionOutcomes::simulate(holder("0")));
+    }
+
+    #[test]
+    fn test_invalid_inputs_say_what_is_wrong() {
+        let holder = |player_cards: Vec<Card>, dealer_card: Vec<Card>, num_decks: &str, bet_size: &str| UserDataStateHolder::new(
+            player_cards,
+            dealer_card,
+            num_decks.to_string(),
+            bet_size.to_string(),
+            "100".to_string()
+        );
+        let error = |holder| parse_state(holder).err().map(|error| error.to_string()).unwrap_or_default();
+
+        assert!(error(holder(vec![Card::Ace, Card::Two], vec![Card::Jack], "six", "10")).starts_with("num_decks is not a valid whole number"));
+        assert!(error(holder(vec![Card::Ace, Card::Two], vec![Card::Jack], "6", "ten")).starts_with("bet_size is not a valid number"));
+        assert_eq!("the player needs at least two cards", error(holder(vec![Card::Ace], vec![Card::Jack], "6", "10")));
+        assert_eq!("the dealer needs at least one card", error(holder(vec![Card::Ace, Card::Two], vec![Card::Empty], "6", "10")));
+        assert_eq!("", error(holder(vec![Card::Ace, Card::Two], vec![Card::Jack], "6", "10")));
+
+        for bet_size in ["-5", "0", "inf"] {
+            let bet_error = error(holder(vec![Card::Ace, Card::Two], vec![Card::Jack], "6", bet_size));
+            assert!(bet_error.starts_with("a bet of"), "Expected {:?} to be rejected but got {:?}", bet_size, bet_error);
+        }
+    }
+
+    #[test]
+    fn test_each_failure_has_its_own_error_kind() {
+        let holder = |player_cards: Vec<Card>, num_decks: &str, bet_size: &str, num_sims: &str| UserDataStateHolder::new(
+            player_cards,
+            vec![Card::Jack],
+            num_decks.to_string(),
+            bet_size.to_string(),
+            num_sims.to_string()
+        );
+        let kind = |holder| parse_state(holder).err().map(|error| error.kind);
+        let two_cards = || vec![Card::Ace, Card::Two];
+
+        assert_eq!(Some(SimulationErrorKind::ParseDecks), kind(holder(two_cards(), "six", "10", "100")));
+        assert_eq!(Some(SimulationErrorKind::ParseBet), kind(holder(two_cards(), "6", "ten", "100")));
+        assert_eq!(Some(SimulationErrorKind::ParseSims), kind(holder(two_cards(), "6", "10", "-1")));
+        assert_eq!(Some(SimulationErrorKind::InvalidHand), kind(holder(vec![Card::Ace], "6", "10", "100")));
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), kind(holder(two_cards(), "0", "10", "100")));
+        assert_eq!(Some(SimulationErrorKind::DeckExhausted), kind(holder(vec![Card::Ace; 5], "1", "10", "100")));
+        assert_eq!(None, kind(holder(two_cards(), "6", "10", "100")));
+    }
+
+    #[test]
+    fn test_errors_outside_the_state_have_their_own_kinds() {
+        fn kind<T>(result: Result<T, SimulationError>) -> Option<SimulationErrorKind> {
+            result.err().map(|error| error.kind)
+        }
+        let action_outcomes = ActionOutcomes::new();
+        let data = |player_cards: Vec<Card>| UserDataState {
+            current_cards: player_cards,
+            dealer_card: vec![Card::Seven],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 100,
+            ..Default::default()
+        };
+
+        assert_eq!(Some(SimulationErrorKind::ParseCards), kind(parse_hand("A X")));
+        assert_eq!(Some(SimulationErrorKind::UnknownAction), kind(parse_action("hit_forever")));
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), kind(check_at_least_one("batch_size", 0)));
+        assert_eq!(None, kind(check_at_least_one("snapshot_every", 1)));
+
+        let twenty_five = UserDataState { rules: RulesConfig { target_total: 25, ..Default::default() }, ..data(vec![Card::Ten, Card::Six]) };
+        assert_eq!(Some(SimulationErrorKind::NotApplicable),
+                   kind(action_outcomes.generate_player_totals_resolution(&twenty_five, BlackJackAction::STAND)));
+        assert_eq!(Some(SimulationErrorKind::NotApplicable),
+                   kind(action_outcomes.generate_double_for(&data(vec![Card::Two, Card::Three, Card::Four]), 5.0)));
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), kind(action_outcomes.generate_double_for(&data(vec![Card::Ten, Card::Six]), 20.0)));
+        assert_eq!(None, kind(action_outcomes.generate_double_for(&data(vec![Card::Ten, Card::Six]), 5.0)));
+        assert_eq!(Some(SimulationErrorKind::NotApplicable), kind(natural_outcome_of(&data(vec![Card::Ten, Card::Six]))));
+        assert_eq!(None, kind(natural_outcome_of(&data(vec![Card::Ace, Card::King]))));
+
+        let sixteen_vs_ten = hand_deviation_index(vec![Card::Ten, Card::Six], Card::Ten, 6).unwrap();
+        assert!(sixteen_vs_ten.abs() < 1.5, "Got {:?}", sixteen_vs_ten);
+        // standing on a hard 4 is never better, whatever the count
+        assert_eq!(Some(SimulationErrorKind::NotApplicable), kind(hand_deviation_index(vec![Card::Two, Card::Two], Card::Six, 6)));
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), kind(hand_deviation_index(vec![Card::Ten, Card::Six], Card::Ten, 0)));
+
+        let rules = RulesConfig::default();
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), kind(check_rounds(&rules, 0, 100).map_err(SimulationError::from)));
+        assert_eq!(Some(SimulationErrorKind::InvalidSettings), kind(check_rounds(&rules, 6, 0).map_err(SimulationError::from)));
+        assert_eq!(Ok(()), check_rounds(&rules, 6, 100));
+
+        let mut outcomes = ActionOutcomes::new();
+        outcomes.stand.estimated_value = f64::NAN;
+        assert_eq!(Some(SimulationErrorKind::InvalidOutcomes), kind(outcomes.check_outcomes().map_err(SimulationError::from)));
+    }
+
+    #[test]
+    fn test_batch_marks_only_the_invalid_state_as_an_error() {
+        let holder = |player_cards: Vec<Card>, dealer_card: Card, bet_size: &str| {
+            let mut holder = UserDataStateHolder::new(
+                player_cards,
+                vec![dealer_card],
+                "6".to_string(),
+                bet_size.to_string(),
+                "500".to_string()
+            );
+            holder.set_seed(Some(559));
+            holder
+        };
+        let mut action_outcomes = ActionOutcomes::new();
+        action_outcomes.set_as_percent(true);
+
+        let batch = action_outcomes.simulate_batch(vec![
+            holder(vec![Card::Ten, Card::Six], Card::Ten, "10"),
+            holder(vec![Card::Ten, Card::Six], Card::Ten, "ten"),
+            holder(vec![Card::Eight, Card::Three], Card::Five, "10")
+        ]);
+        assert_eq!(3, batch.len());
+        assert_eq!(Some(SimulationErrorKind::ParseBet), batch[1].as_ref().err().map(|error| error.kind));
+
+        // the valid states come out as they would on their own, in the batch's output units
+        for (entry, cards, dealer_card) in [(&batch[0], vec![Card::Ten, Card::Six], Card::Ten),
+                                            (&batch[2], vec![Card::Eight, Card::Three], Card::Five)] {
+            let (outcomes, metadata) = entry.as_ref().unwrap();
+            let data = parse_state(holder(cards, dealer_card, "10")).unwrap();
+            let mut alone = ActionOutcomes { as_percent: true, ..ActionOutcomes::new() };
+            alone.populate_action_outcomes(&data);
+            alone.apply_output_units();
+            assert_eq!(&alone, outcomes);
+            assert_eq!(500, metadata.num_sims);
+        }
+    }
+
+    #[test]
+    fn test_dealer_starting_from_two_cards_draws_on_from_them() {
+        let action_outcomes = ActionOutcomes::new();
+        let data = UserDataState {
+            current_cards: vec![Card::Ten, Card::Queen],
+            dealer_card: vec![Card::Ten, Card::Seven],
+            num_decks: 6,
+            bet_size: 10.0,
+            num_sims: 1_000,
+            ..Default::default()
+        };
+        assert_eq!(Ok(()), data.validate());
+
+        // a dealer 17 already stands, so 20 always wins
+        let stand = action_outcomes.generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!((1.0, 0.0), (stand.win, stand.loss));
+
+        // while on 16 they have to draw, and 17 to 21 are all the dealer can finish on
+        let sixteen = UserDataState { dealer_card: vec![Card::Ten, Card::Six], ..data };
+        let stand = action_outcomes.generate_outcomes_with(&sixteen, BlackJackAction::STAND, &mut |_, dealer_total, _| {
+            assert!(dealer_total.is_none_or(|total| (17..=21).contains(&total)), "Got {:?}", dealer_total);
+        });
+        assert!(stand.win > 0.5 && stand.loss > 0.0, "Got {:?}", stand);
+        assert!(!action_outcomes.generate_insurance_outcome(&sixteen).applicable);
+
+        let bust = UserDataState { dealer_card: vec![Card::Ten, Card::Six, Card::King], ..sixteen };
+        assert_eq!(Err(InvalidStateError::DealerBust(26)), bust.validate());
+    }
+
     #[test]
     fn test_holder_empty_cards_are_removed() {
         let user_data = UserDataStateHolder::new(
@@ -674,4 +7423,28 @@ mod tests {
             Err(_) => panic!("Test failed: Should not Err"),
         }
     }
+
+    #[test]
+    fn test_known_hole_card_makes_a_dealer_17_that_stands() {
+        let holder = || UserDataStateHolder::new(
+            vec![Card::Ten, Card::Eight],
+            vec![Card::Six],
+            "1".to_string(),
+            "10".to_string(),
+            "1000".to_string()
+        );
+        let mut with_hole_card = holder();
+        with_hole_card.set_dealer_hole_card(Card::Ace);
+        let data = parse_state(with_hole_card).expect("Test failed: Should parse");
+        assert_eq!(vec![Card::Six, Card::Ace], data.dealer_card);
+        assert_eq!(48, remaining_deck(&data).cards.len());
+
+        // a soft 17 stands under the default rules, so 18 always wins
+        let stand = ActionOutcomes::new().generate_outcomes(&data, BlackJackAction::STAND);
+        assert_eq!((1.0, 0.0), (stand.win, stand.loss));
+
+        let mut unknown = holder();
+        unknown.set_dealer_hole_card(Card::Empty);
+        assert_eq!(vec![Card::Six], parse_state(unknown).expect("Test failed: Should parse").dealer_card);
+    }
 }
\ No newline at end of file
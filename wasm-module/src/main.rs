@@ -0,0 +1,42 @@
+//! Runs the simulation from the terminal and prints every action's outcomes as JSON, e.g.
+//! `cargo run --release -- "10 6" 10 6 100 100000` for 16 against a dealer 10 with six
+//! decks, a $100 bet and 100,000 simulations per action
+
+use std::process::ExitCode;
+use wasm_module::{parse_card_tokens, ActionOutcomes, UserDataStateHolder};
+
+const USAGE: &str = "usage: wasm-module <player cards> <dealer card> [num decks] [bet size] [num sims]\n\
+    cards are shorthand like \"A 10\" or \"AS,TH\", and the defaults are 6 decks, a bet of 10 and 10000 sims";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Parses the arguments and simulates them, returning the JSON to print
+fn run(args: &[String]) -> Result<String, String> {
+    let (player_cards, dealer_card) = match args {
+        [player_cards, dealer_card, ..] if args.len() <= 5 => (player_cards, dealer_card),
+        _ => return Err("expected between 2 and 5 arguments".to_string())
+    };
+    let optional_arg = |index: usize, default: &str| args.get(index).cloned().unwrap_or(default.to_string());
+
+    let data = UserDataStateHolder::new(
+        parse_card_tokens(player_cards)?,
+        parse_card_tokens(dealer_card)?,
+        optional_arg(2, "6"),
+        optional_arg(3, "10"),
+        optional_arg(4, "10000")
+    );
+    let action_outcomes = ActionOutcomes::simulate(data)?;
+    serde_json::to_string_pretty(&action_outcomes).map_err(|error| error.to_string())
+}